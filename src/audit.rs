@@ -0,0 +1,246 @@
+use crate::config::config;
+use crate::hook::create_shared_dir;
+use crate::ops::{self, chmod};
+use crate::output::{self, Format, Tabular};
+use crate::repo::Repository;
+use crate::repo_config::ref_matches;
+use crate::system::system;
+use crate::time::format_utc;
+use crate::{dry_run, err, DResult};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A ref update checked by the hook, stored in the repository push log (gmg/audit.log, JSON
+/// lines). Hooks run as the pushing user, so they write records to the spool (gmg/audit), which
+/// are moved to the log by gmg
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PushRecord {
+    pub timestamp: u64,
+    pub user: String,
+    #[serde(rename = "ref")]
+    pub refname: String,
+    pub oldrev: String,
+    pub newrev: String,
+    /// non-fast-forward update
+    pub forced: bool,
+    pub accepted: bool,
+    /// rejection reason
+    pub reason: Option<String>,
+}
+
+impl Tabular for PushRecord {
+    fn header() -> &'static [&'static str] {
+        &[
+            "timestamp",
+            "user",
+            "ref",
+            "oldrev",
+            "newrev",
+            "forced",
+            "accepted",
+            "reason",
+        ]
+    }
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.timestamp.to_string(),
+            self.user.clone(),
+            self.refname.clone(),
+            self.oldrev.clone(),
+            self.newrev.clone(),
+            self.forced.to_string(),
+            self.accepted.to_string(),
+            self.reason.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+#[derive(Default)]
+pub struct Filter {
+    pub user: Option<String>,
+    /// branch name, full ref name or a glob pattern
+    pub refname: Option<String>,
+    pub since: Option<u64>,
+    pub rejected: bool,
+}
+
+impl Filter {
+    pub fn matches(&self, record: &PushRecord) -> bool {
+        self.user.as_ref().is_none_or(|u| *u == record.user)
+            && self
+                .refname
+                .as_ref()
+                .is_none_or(|r| ref_matches(r, &record.refname))
+            && self.since.is_none_or(|t| record.timestamp >= t)
+            && (!self.rejected || !record.accepted)
+    }
+}
+
+fn log_path(git_dir: &Path) -> PathBuf {
+    git_dir.join("gmg/audit.log")
+}
+
+fn spool_path(git_dir: &Path) -> PathBuf {
+    git_dir.join("gmg/audit")
+}
+
+/// Sets permissions of the push log and the spool, called by repository fix. The spool is
+/// writable by all repository users but, as well as in the parent directory, users can not
+/// remove or replace files of others
+pub fn set_permissions(git_dir: &Path) -> DResult<()> {
+    let spool = spool_path(git_dir);
+    ops::create_dir_all(&spool)?;
+    chmod(&spool, 0o3775)?;
+    chmod(git_dir.join("gmg"), 0o3775)?;
+    let path = log_path(git_dir);
+    if path.exists() {
+        chmod(&path, 0o644)?;
+    }
+    Ok(())
+}
+
+/// Writes the record to the spool
+pub fn append(git_dir: &Path, record: &PushRecord) -> DResult<()> {
+    let dir = spool_path(git_dir);
+    if !dir.exists() {
+        create_shared_dir(&dir)?;
+        chmod(&dir, 0o3775)?;
+    }
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    // sorted by time
+    let id = format!(
+        "{}.{:09}-{}",
+        time.as_secs(),
+        time.subsec_nanos(),
+        std::process::id()
+    );
+    let tmp = dir.join(format!(".{}.tmp", id));
+    fs::write(&tmp, serde_json::to_vec(record)?)?;
+    chmod(&tmp, 0o644)?;
+    fs::rename(tmp, dir.join(format!("{}.json", id)))?;
+    Ok(())
+}
+
+/// Spooled records, the oldest first
+fn spooled(git_dir: &Path) -> DResult<Vec<PathBuf>> {
+    let dir = spool_path(git_dir);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut result = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "json") {
+            result.push(path);
+        }
+    }
+    result.sort();
+    Ok(result)
+}
+
+/// Reads a spooled record, records written by another user than the pusher (except root) are
+/// rejected
+fn read_spooled(path: &Path) -> DResult<Option<PushRecord>> {
+    let owner = fs::symlink_metadata(path)?.uid();
+    let Ok(record) = serde_json::from_slice::<PushRecord>(&fs::read(path)?) else {
+        return Ok(None);
+    };
+    if owner != 0
+        && users::get_user_by_uid(owner).is_none_or(|u| u.name().to_string_lossy() != record.user)
+    {
+        return Ok(None);
+    }
+    Ok(Some(record))
+}
+
+/// Moves the spooled records to the log, which is writable by the git user only. Requires root
+pub fn collect(git_dir: &Path) -> DResult<()> {
+    let spooled = spooled(git_dir)?;
+    if spooled.is_empty() {
+        return Ok(());
+    }
+    let path = log_path(git_dir);
+    let created = !path.exists();
+    let mut f = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?;
+    if created {
+        chmod(&path, 0o644)?;
+        system().chown(&path, &config().git_user, None, false)?;
+    }
+    for entry in spooled {
+        if let Some(record) = read_spooled(&entry)? {
+            let mut line = serde_json::to_vec(&record)?;
+            line.push(b'\n');
+            f.write_all(&line)?;
+        } else {
+            err!(format!(
+                "invalid push log record skipped: {}",
+                entry.to_string_lossy()
+            ));
+        }
+        fs::remove_file(entry)?;
+    }
+    Ok(())
+}
+
+/// Records of the log and the spool, the oldest first. Broken lines (e.g. of interrupted
+/// writes) are skipped
+pub fn read(git_dir: &Path) -> DResult<Vec<PushRecord>> {
+    let path = log_path(git_dir);
+    let mut result: Vec<PushRecord> = if path.exists() {
+        fs::read_to_string(path)?
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()
+    } else {
+        Vec::new()
+    };
+    for entry in spooled(git_dir)? {
+        if let Some(record) = read_spooled(&entry)? {
+            result.push(record);
+        }
+    }
+    Ok(result)
+}
+
+/// Prints the push log, the spooled records are moved to the log first if running as root
+pub fn print(repo: &Repository, filter: &Filter, format: Format) -> DResult<()> {
+    repo.exists()?;
+    if !dry_run() && users::get_current_uid() == 0 {
+        collect(repo.path())?;
+    }
+    let records: Vec<PushRecord> = read(repo.path())?
+        .into_iter()
+        .filter(|r| filter.matches(r))
+        .collect();
+    if !format.is_human() {
+        return output::print_list(format, &records);
+    }
+    for r in records {
+        let mut line = format!(
+            "{} {} {} {}..{}",
+            format_utc(r.timestamp),
+            r.user.yellow(),
+            r.refname,
+            &r.oldrev[..r.oldrev.len().min(7)],
+            &r.newrev[..r.newrev.len().min(7)]
+        );
+        if r.forced {
+            line.push_str(&format!(" {}", "(forced)".magenta()));
+        }
+        if let Some(reason) = r.reason {
+            line.push_str(&format!(" {}: {}", "rejected".red().bold(), reason));
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
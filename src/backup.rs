@@ -0,0 +1,166 @@
+use crate::ops::{self, chmod};
+use crate::repo::Repository;
+use crate::time::now;
+use crate::{cmd, failed, DResult, ErrorKind, VERSION};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Repository config keys which are not backed up: set on create or the archive state
+fn is_local_key(key: &str) -> bool {
+    matches!(key, "gmg.group" | "gmg.version" | "gmg.archived") || key.starts_with("gmg.archive.")
+}
+
+/// Repository metadata, stored next to the bundle as <name>.json
+#[derive(Serialize, Deserialize)]
+pub struct Metadata {
+    pub name: String,
+    pub description: Option<String>,
+    /// the ref HEAD points to
+    pub head: Option<String>,
+    /// hooks.* and gmg.* keys (protection, maintainers, RCI, webhooks, grants), in the config
+    /// order
+    pub config: Vec<(String, String)>,
+    /// repository members, including the former ones of archived repositories
+    pub users: Vec<String>,
+    /// the repository has no refs, the bundle is not created
+    pub empty: bool,
+    pub created: u64,
+    pub version: String,
+}
+
+impl Metadata {
+    fn new(repo: &Repository) -> DResult<Self> {
+        let config = repo.read_config()?;
+        let mut users: Vec<String> = repo.users()?.iter().map(|u| u.login().to_owned()).collect();
+        users.extend(config.archived_members());
+        users.sort();
+        users.dedup();
+        let head = cmd!("git", "symbolic-ref", "-q", "HEAD")
+            .dir(repo.path())
+            .run_any()?;
+        let refs = cmd!("git", "for-each-ref", "--count=1")
+            .dir(repo.path())
+            .run()?;
+        Ok(Self {
+            name: repo.name().to_owned(),
+            description: repo.read_description()?,
+            head: head
+                .success()
+                .then(|| head.stdout.trim().to_owned())
+                .filter(|h| !h.is_empty()),
+            config: config
+                .entries()
+                .iter()
+                .filter(|(k, _)| {
+                    (k.starts_with("hooks.") || k.starts_with("gmg.")) && !is_local_key(k)
+                })
+                .cloned()
+                .collect(),
+            users,
+            empty: refs.stdout.trim().is_empty(),
+            created: now(),
+            version: VERSION.to_owned(),
+        })
+    }
+}
+
+/// Backs up the repositories (all if not specified) into the directory, as <name>.bundle (all
+/// refs) and <name>.json (metadata). The files are readable by root only, as the metadata
+/// contains RCI and webhook secrets
+pub fn backup(dir: &Path, repos: Vec<Repository>) -> DResult<()> {
+    let repos = if repos.is_empty() {
+        Repository::list()?
+    } else {
+        repos
+    };
+    for repo in repos {
+        repo.exists()?;
+        let meta = Metadata::new(&repo)?;
+        let bundle = dir.join(format!("{}.bundle", repo.name()));
+        if let Some(parent) = bundle.parent() {
+            ops::create_dir_all(parent)?;
+        }
+        if !meta.empty {
+            cmd!("git", "bundle", "create", "-q", &bundle, "--all")
+                .dir(repo.path())
+                .mutating()
+                .run()?;
+            chmod(&bundle, 0o600)?;
+        }
+        let meta_path = bundle.with_extension("json");
+        ops::write(&meta_path, serde_json::to_vec_pretty(&meta)?)?;
+        chmod(&meta_path, 0o600)?;
+        println!(
+            "Repository {} {} to {}",
+            repo.name_colored(),
+            "backed up".green().bold(),
+            bundle.to_string_lossy()
+        );
+    }
+    Ok(())
+}
+
+/// Restores the repository from the bundle and its metadata file, the name can be overridden.
+/// Grants are re-applied to the users which exist
+pub fn restore(bundle: &Path, name: Option<&Repository>) -> DResult<()> {
+    let bundle: PathBuf = if bundle.extension().is_some_and(|e| e == "json") {
+        bundle.with_extension("bundle")
+    } else {
+        bundle.to_owned()
+    };
+    let meta_path = bundle.with_extension("json");
+    let meta: Metadata = match fs::read(&meta_path) {
+        Ok(data) => serde_json::from_slice(&data)?,
+        Err(e) => {
+            return failed!(
+                NotFound,
+                format!(
+                    "unable to read backup metadata {}: {}",
+                    meta_path.to_string_lossy(),
+                    e
+                )
+            )
+        }
+    };
+    let repo = match name {
+        Some(repo) => repo.clone(),
+        None => meta.name.parse()?,
+    };
+    if !meta.empty {
+        // checked before the repository is created
+        cmd!("git", "bundle", "list-heads", &bundle).run()?;
+    }
+    repo.create(true, None)?;
+    if !meta.empty {
+        cmd!("git", "fetch", "-q", &bundle, "refs/*:refs/*")
+            .dir(repo.path())
+            .mutating()
+            .run()?;
+    }
+    if let Some(ref head) = meta.head {
+        cmd!("git", "symbolic-ref", "HEAD", head)
+            .dir(repo.path())
+            .mutating()
+            .run()?;
+    }
+    repo.set_description(meta.description.as_deref())?;
+    // namespace grants are applied on create already
+    let current = repo.read_config()?;
+    for (key, value) in &meta.config {
+        if !current.get_all(key).contains(value) {
+            repo.add(key, value)?;
+        }
+    }
+    // the fetched objects are owned by root
+    repo.fix(false)?;
+    repo.attach_former(&meta.users)?;
+    println!(
+        "Repository {} {} from {}",
+        repo.name_colored(),
+        "restored".green().bold(),
+        bundle.to_string_lossy()
+    );
+    Ok(())
+}
@@ -0,0 +1,159 @@
+use crate::{dry_run, ops, DResult, ErrorKind, VERBOSE};
+use colored::Colorize;
+use std::ffi::{OsStr, OsString};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic;
+
+/// Builds a command from a program name and arguments, the arguments are passed to the process
+/// as-is and are never parsed by a shell
+#[macro_export]
+macro_rules! cmd {
+    ($program: expr $(, $arg: expr)* $(,)?) => {
+        $crate::cmd::Cmd::new($program)$(.arg($arg))*
+    };
+}
+
+pub struct Cmd {
+    program: OsString,
+    args: Vec<OsString>,
+    dir: Option<PathBuf>,
+    input: Option<Vec<u8>>,
+    mutating: bool,
+}
+
+pub struct Output {
+    pub code: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Output {
+    #[inline]
+    pub fn success(&self) -> bool {
+        self.code == 0
+    }
+}
+
+/// Quotes the string for a shell if required
+pub fn quote(s: &str) -> String {
+    if !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c))
+    {
+        s.to_owned()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+impl Cmd {
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
+        Self {
+            program: program.as_ref().to_owned(),
+            args: Vec::new(),
+            dir: None,
+            input: None,
+            mutating: false,
+        }
+    }
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+    /// Sets the working directory of the process
+    pub fn dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.dir = Some(dir.as_ref().to_owned());
+        self
+    }
+    /// Data written to the process stdin
+    pub fn input<D: Into<Vec<u8>>>(mut self, data: D) -> Self {
+        self.input = Some(data.into());
+        self
+    }
+    /// Marks the command as modifying the server, such commands are not executed in dry-run mode
+    pub fn mutating(mut self) -> Self {
+        self.mutating = true;
+        self
+    }
+    /// The command line, quoted for display only
+    pub fn command_line(&self) -> String {
+        let mut result = quote(&self.program.to_string_lossy());
+        for arg in &self.args {
+            result.push(' ');
+            result.push_str(&quote(&arg.to_string_lossy()));
+        }
+        result
+    }
+    /// Runs the command and fails if the exit code is not zero
+    pub fn run(&self) -> DResult<Output> {
+        let output = self.run_any()?;
+        if output.success() {
+            Ok(output)
+        } else {
+            Err(Box::new(ErrorKind::Exec {
+                command: self.command_line(),
+                code: output.code,
+                stderr: output.stderr,
+            }))
+        }
+    }
+    /// Runs the command, ignoring the exit code
+    pub fn run_any(&self) -> DResult<Output> {
+        if self.mutating && dry_run() {
+            ops::report(self.command_line());
+            return Ok(Output {
+                code: 0,
+                stdout: String::new(),
+                stderr: String::new(),
+            });
+        }
+        if VERBOSE.load(atomic::Ordering::SeqCst) {
+            println!("> {}", self.command_line().dimmed().bold());
+        }
+        let mut command = std::process::Command::new(&self.program);
+        command
+            .args(&self.args)
+            .stdin(if self.input.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(ref dir) = self.dir {
+            command.current_dir(dir);
+        }
+        let output = match command.spawn().and_then(|mut child| {
+            let stdin = child.stdin.take();
+            // stdin is written from a separate thread, otherwise large input deadlocks when the
+            // process fills the output pipes
+            std::thread::scope(|scope| {
+                let writer = scope.spawn(|| match (&self.input, stdin) {
+                    (Some(input), Some(mut stdin)) => stdin.write_all(input),
+                    _ => Ok(()),
+                });
+                let output = child.wait_with_output()?;
+                match writer.join() {
+                    Ok(Err(e)) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(e),
+                    _ => Ok(output),
+                }
+            })
+        }) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(Box::new(ErrorKind::Exec {
+                    command: self.command_line(),
+                    code: -1,
+                    stderr: e.to_string(),
+                }))
+            }
+        };
+        Ok(Output {
+            code: output.status.code().unwrap_or(-1),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+}
@@ -0,0 +1,302 @@
+use crate::key::KEY_TYPES;
+use crate::{failed, DResult, ErrorKind};
+use colored::Colorize;
+use configparser::ini::Ini;
+use lazy_static::lazy_static;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/gmg/gmg.conf";
+pub const CONFIG_ENV: &str = "GMG_CONFIG";
+
+const SECTION: &str = "gmg";
+
+// ssh-dss is considered weak and is not allowed by default
+const DEFAULT_KEY_TYPES: &[&str] = &[
+    "ssh-ed25519",
+    "sk-ssh-ed25519@openssh.com",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+    "ssh-rsa",
+];
+
+// POSIX group names are limited to 32 chars
+pub const MAX_GROUP_LEN: usize = 32;
+// hex chars of the hash used for groups of long repository names
+const GROUP_HASH_LEN: usize = 12;
+
+lazy_static! {
+    static ref CONFIG: RwLock<Arc<Config>> = RwLock::new(Arc::new(Config::default()));
+}
+
+/// Server layout, loaded from the config file at startup
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub source: Option<PathBuf>,
+    pub git_path: PathBuf,
+    pub home_path: PathBuf,
+    pub git_user: String,
+    pub group_prefix: String,
+    /// POSIX group prefix of teams
+    pub team_prefix: String,
+    pub main_branch: String,
+    pub protected_branches: Vec<String>,
+    /// SSH key types accepted for users
+    pub key_types: Vec<String>,
+    pub min_rsa_bits: u32,
+    /// gmg binary called by the repository hooks, the current executable if not set
+    pub gmg_path: Option<PathBuf>,
+    /// sendmail-compatible binary for push notifications, the message is piped to its stdin
+    pub sendmail: PathBuf,
+    /// From header of push notifications, set by sendmail if not specified
+    pub mail_from: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            source: None,
+            git_path: Path::new("/git").to_owned(),
+            home_path: Path::new("/home").to_owned(),
+            git_user: "git".to_owned(),
+            group_prefix: "g_".to_owned(),
+            team_prefix: "t_".to_owned(),
+            main_branch: "main".to_owned(),
+            protected_branches: vec!["main".to_owned()],
+            key_types: DEFAULT_KEY_TYPES.iter().map(|&t| t.to_owned()).collect(),
+            min_rsa_bits: 2048,
+            gmg_path: None,
+            sendmail: Path::new("/usr/sbin/sendmail").to_owned(),
+            mail_from: None,
+        }
+    }
+}
+
+/// # Panics
+///
+/// Will panic if the config lock is poisoned
+pub fn config() -> Arc<Config> {
+    CONFIG.read().unwrap().clone()
+}
+
+/// # Panics
+///
+/// Will panic if the config lock is poisoned
+pub fn set_config(config: Config) {
+    *CONFIG.write().unwrap() = Arc::new(config);
+}
+
+/// Repository and user names are parsed by clap, which requires the config to be loaded before
+/// the command line is, so the config path is looked up in the raw args
+pub fn path_from_args<I: IntoIterator<Item = OsString>>(args: I) -> Option<PathBuf> {
+    let mut args = args.into_iter().skip(1);
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy();
+        if arg == "--" {
+            break;
+        } else if arg == "-c" || arg == "--config" {
+            return args.next().map(PathBuf::from);
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        } else if let Some(flags) = arg.strip_prefix('-').filter(|f| !f.starts_with('-')) {
+            // combined short flags, e.g. -nc path or -ncpath
+            for (i, flag) in flags.char_indices() {
+                match flag {
+                    'c' => {
+                        let value = &flags[i + 1..];
+                        let value = value.strip_prefix('=').unwrap_or(value);
+                        if value.is_empty() {
+                            return args.next().map(PathBuf::from);
+                        }
+                        return Some(PathBuf::from(value));
+                    }
+                    'n' | 'v' => {}
+                    // the rest is a value of another flag (e.g. -ojson)
+                    _ => break,
+                }
+            }
+        }
+    }
+    std::env::var_os(CONFIG_ENV).map(PathBuf::from)
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+fn valid_name(s: &str) -> bool {
+    !s.is_empty()
+        && !s.starts_with('-')
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+fn valid_branch(s: &str) -> bool {
+    !s.is_empty()
+        && !s.starts_with('-')
+        && !s.starts_with('/')
+        && !s.ends_with('/')
+        && !s.ends_with(".lock")
+        && !s.contains("..")
+        && !s.contains("//")
+        && !s
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || "~^:?*[\\".contains(c))
+}
+
+/// Checks a branch name or a glob pattern of branches
+pub fn valid_branch_rule(s: &str) -> bool {
+    valid_branch(&s.replace(['*', '?', '[', ']'], "x"))
+}
+
+impl Config {
+    /// Loads the config from the file specified. If no path is specified, the default one is
+    /// used, if exists
+    pub fn load(path: Option<&Path>) -> DResult<Self> {
+        let mut config = Config::default();
+        let path = if let Some(p) = path {
+            p
+        } else {
+            let p = Path::new(DEFAULT_CONFIG_PATH);
+            if !p.exists() {
+                return Ok(config);
+            }
+            p
+        };
+        let mut ini = Ini::new();
+        if let Err(e) = ini.load(path) {
+            return failed!(
+                Config,
+                format!("unable to load config {}: {}", path.to_string_lossy(), e)
+            );
+        }
+        for (key, value) in ini.get_map_ref().get(SECTION).into_iter().flatten() {
+            let value = value.as_deref().unwrap_or_default().trim();
+            match key.as_str() {
+                "git_path" => config.git_path = PathBuf::from(value),
+                "home_path" => config.home_path = PathBuf::from(value),
+                "git_user" => config.git_user = value.to_owned(),
+                "group_prefix" => config.group_prefix = value.to_owned(),
+                "team_prefix" => config.team_prefix = value.to_owned(),
+                "main_branch" => config.main_branch = value.to_owned(),
+                "protected_branches" => config.protected_branches = split_list(value),
+                "key_types" => config.key_types = split_list(value),
+                "min_rsa_bits" => {
+                    config.min_rsa_bits = value.parse().map_err(|e| {
+                        ErrorKind::Config(format!("invalid min_rsa_bits {}: {}", value, e))
+                    })?;
+                }
+                "gmg_path" => config.gmg_path = Some(PathBuf::from(value)),
+                "sendmail" => config.sendmail = PathBuf::from(value),
+                "mail_from" => config.mail_from = Some(value.to_owned()),
+                _ => return failed!(Config, format!("unknown config key: {}", key)),
+            }
+        }
+        config.source = Some(path.to_owned());
+        config.validate()?;
+        Ok(config)
+    }
+    pub fn validate(&self) -> DResult<()> {
+        if !self.git_path.is_absolute() {
+            return failed!(Config, "git_path must be absolute".to_owned());
+        }
+        if !self.home_path.is_absolute() {
+            return failed!(Config, "home_path must be absolute".to_owned());
+        }
+        if self.gmg_path.as_ref().is_some_and(|p| !p.is_absolute()) {
+            return failed!(Config, "gmg_path must be absolute".to_owned());
+        }
+        if !valid_name(&self.git_user) {
+            return failed!(Config, format!("invalid git_user: {}", self.git_user));
+        }
+        if !valid_name(&self.group_prefix) {
+            return failed!(
+                Config,
+                format!("invalid group_prefix: {}", self.group_prefix)
+            );
+        }
+        if self.group_hash_len() < 8 {
+            return failed!(Config, "group_prefix is too long".to_owned());
+        }
+        if !valid_name(&self.team_prefix) {
+            return failed!(Config, format!("invalid team_prefix: {}", self.team_prefix));
+        }
+        if self.max_team_name_len() < 8 {
+            return failed!(Config, "team_prefix is too long".to_owned());
+        }
+        if self.team_prefix.starts_with(&self.group_prefix)
+            || self.group_prefix.starts_with(&self.team_prefix)
+        {
+            return failed!(
+                Config,
+                "group_prefix and team_prefix must not overlap".to_owned()
+            );
+        }
+        if !valid_branch(&self.main_branch) {
+            return failed!(Config, format!("invalid main_branch: {}", self.main_branch));
+        }
+        for branch in &self.protected_branches {
+            if !valid_branch_rule(branch) {
+                return failed!(Config, format!("invalid protected branch: {}", branch));
+            }
+        }
+        if self.key_types.is_empty() {
+            return failed!(Config, "no key types allowed".to_owned());
+        }
+        for key_type in &self.key_types {
+            if !KEY_TYPES.contains(&key_type.as_str()) {
+                return failed!(Config, format!("unsupported key type: {}", key_type));
+            }
+        }
+        Ok(())
+    }
+    /// Length of the hash, used as the group name suffix for repositories with long names
+    pub fn group_hash_len(&self) -> usize {
+        MAX_GROUP_LEN
+            .saturating_sub(self.group_prefix.len())
+            .min(GROUP_HASH_LEN)
+    }
+    /// Destroyed repositories, kept inside the git directory to be moved on the same file system
+    pub fn trash_path(&self) -> PathBuf {
+        self.git_path.join(".trash")
+    }
+    pub fn max_team_name_len(&self) -> usize {
+        MAX_GROUP_LEN.saturating_sub(self.team_prefix.len())
+    }
+    pub fn print(&self) {
+        println!(
+            "config: {}",
+            self.source
+                .as_ref()
+                .map_or_else(
+                    || "(defaults)".to_owned(),
+                    |p| p.to_string_lossy().to_string()
+                )
+                .white()
+        );
+        println!("git_path: {}", self.git_path.to_string_lossy().white());
+        println!("home_path: {}", self.home_path.to_string_lossy().white());
+        println!("git_user: {}", self.git_user.yellow());
+        println!("group_prefix: {}", self.group_prefix);
+        println!("team_prefix: {}", self.team_prefix);
+        println!("main_branch: {}", self.main_branch.yellow());
+        println!("protected_branches: {}", self.protected_branches.join(","));
+        println!("key_types: {}", self.key_types.join(","));
+        println!("min_rsa_bits: {}", self.min_rsa_bits);
+        if let Some(ref gmg_path) = self.gmg_path {
+            println!("gmg_path: {}", gmg_path.to_string_lossy().white());
+        }
+        println!("sendmail: {}", self.sendmail.to_string_lossy().white());
+        if let Some(ref mail_from) = self.mail_from {
+            println!("mail_from: {}", mail_from);
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use crate::hook::create_shared_dir;
+use crate::mail;
+use crate::ops::{self, chmod};
+use crate::output::{self, Format, Tabular};
+use crate::repo::Repository;
+use crate::repo_config::RepoConfig;
+use crate::time::{ago, now};
+use crate::webhook::{self, Response};
+use crate::{dry_run, DResult};
+use bmart_derive::EnumStr;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Failed deliveries are given up after
+const MAX_ATTEMPTS: u32 = 10;
+/// Max delay between retries, in seconds
+const MAX_BACKOFF: u64 = 6 * 3600;
+/// Finished deliveries are removed from the spool after, in seconds
+const KEEP: u64 = 30 * 86400;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, EnumStr)]
+#[serde(rename_all = "lowercase")]
+#[enumstr(rename_all = "lowercase")]
+pub enum Kind {
+    Rci,
+    Webhook,
+    Email,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, EnumStr)]
+#[serde(rename_all = "lowercase")]
+#[enumstr(rename_all = "lowercase")]
+pub enum Status {
+    Delivered,
+    /// not sent yet or failed, to be retried
+    Pending,
+    /// failed, given up
+    Failed,
+}
+
+/// A push notification (RCI trigger, webhook or email), stored in the repository spool as
+/// gmg/deliveries/<id>.json
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Delivery {
+    pub id: String,
+    pub kind: Kind,
+    /// webhook name, RCI branch or email address
+    pub target: String,
+    pub event: String,
+    #[serde(rename = "ref")]
+    pub refname: Option<String>,
+    pub status: Status,
+    /// HTTP status code of the last attempt, if a response has been received
+    pub code: Option<u16>,
+    pub error: Option<String>,
+    pub attempts: u32,
+    pub created: u64,
+    pub last_attempt: Option<u64>,
+    pub next_attempt: Option<u64>,
+    /// webhook JSON payload or email message
+    pub payload: Option<serde_json::Value>,
+}
+
+impl Tabular for Delivery {
+    fn header() -> &'static [&'static str] {
+        &[
+            "id",
+            "kind",
+            "target",
+            "event",
+            "ref",
+            "status",
+            "code",
+            "error",
+            "attempts",
+            "created",
+            "last_attempt",
+            "next_attempt",
+        ]
+    }
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.kind.to_string(),
+            self.target.clone(),
+            self.event.clone(),
+            self.refname.clone().unwrap_or_default(),
+            self.status.to_string(),
+            self.code.map(|c| c.to_string()).unwrap_or_default(),
+            self.error.clone().unwrap_or_default(),
+            self.attempts.to_string(),
+            self.created.to_string(),
+            self.last_attempt.map(|t| t.to_string()).unwrap_or_default(),
+            self.next_attempt.map(|t| t.to_string()).unwrap_or_default(),
+        ]
+    }
+}
+
+/// Delay before the next attempt: 1 minute, doubled for each failed attempt
+pub fn backoff(attempts: u32) -> u64 {
+    60u64
+        .saturating_mul(1 << attempts.saturating_sub(1).min(20))
+        .min(MAX_BACKOFF)
+}
+
+fn spool_path(git_dir: &Path) -> PathBuf {
+    git_dir.join("gmg/deliveries")
+}
+
+impl Delivery {
+    pub fn new(
+        kind: Kind,
+        target: &str,
+        event: &str,
+        refname: Option<&str>,
+        payload: Option<serde_json::Value>,
+    ) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let seed = format!("{}:{}:{:?}", target, std::process::id(), time);
+        Self {
+            // sorted by time
+            id: format!(
+                "{}.{:09}-{}",
+                time.as_secs(),
+                time.subsec_nanos(),
+                webhook::hex(&Sha256::digest(seed.as_bytes())[..4])
+            ),
+            kind,
+            target: target.to_owned(),
+            event: event.to_owned(),
+            refname: refname.map(ToOwned::to_owned),
+            status: Status::Pending,
+            code: None,
+            error: None,
+            attempts: 0,
+            created: time.as_secs(),
+            last_attempt: None,
+            next_attempt: None,
+            payload,
+        }
+    }
+    /// Sends the notification with the current repository config, a target removed from the
+    /// config is given up
+    pub fn attempt(&mut self, config: &RepoConfig) -> DResult<()> {
+        let response = match self.kind {
+            Kind::Webhook => config
+                .webhooks()
+                .into_iter()
+                .find(|w| w.name == self.target)
+                .map(|w| {
+                    let body = serde_json::to_vec(&self.payload)?;
+                    webhook::send(&w, &self.event, &self.id, body)
+                }),
+            Kind::Rci => config
+                .rci()
+                .into_iter()
+                .find(|r| r.branch == self.target)
+                .map(|rci| webhook::trigger_rci(&rci)),
+            Kind::Email => config
+                .notifications()
+                .into_iter()
+                .find(|n| n.email == self.target)
+                .map(|n| {
+                    let message = self
+                        .payload
+                        .as_ref()
+                        .and_then(serde_json::Value::as_str)
+                        .unwrap_or_default();
+                    mail::send(&n.email, message)
+                }),
+        };
+        let now = now();
+        self.attempts += 1;
+        self.last_attempt = Some(now);
+        self.next_attempt = None;
+        let Some(response) = response else {
+            self.status = Status::Failed;
+            self.error = Some(format!("{} {} has been removed", self.kind, self.target));
+            return Ok(());
+        };
+        let Response { code, error } = response?;
+        self.code = code;
+        self.status = if error.is_none() {
+            Status::Delivered
+        } else if self.attempts >= MAX_ATTEMPTS {
+            Status::Failed
+        } else {
+            self.next_attempt = Some(now + backoff(self.attempts));
+            Status::Pending
+        };
+        self.error = error;
+        Ok(())
+    }
+    /// Checks if a pending delivery is due for the next attempt
+    pub fn is_due(&self, now: u64) -> bool {
+        self.status == Status::Pending && self.next_attempt.is_none_or(|t| t <= now)
+    }
+    /// Writes the delivery to the spool, the spool is writable by all repository users
+    pub fn save(&self, git_dir: &Path) -> DResult<()> {
+        let dir = spool_path(git_dir);
+        create_shared_dir(&dir)?;
+        let path = dir.join(format!("{}.json", self.id));
+        let tmp = dir.join(format!(".{}.tmp", self.id));
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        chmod(&tmp, 0o664)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+    fn summary(&self) -> String {
+        let mut line = format!("{} {} {}", self.id, self.kind, self.target.yellow());
+        if let Some(ref refname) = self.refname {
+            line.push_str(&format!(" {}", refname));
+        } else {
+            line.push_str(&format!(" {}", self.event));
+        }
+        let status = match self.status {
+            Status::Delivered => self.status.to_string().green(),
+            Status::Pending => self.status.to_string().yellow(),
+            Status::Failed => self.status.to_string().red(),
+        };
+        line.push_str(&format!(" {}", status.bold()));
+        if let Some(ref error) = self.error {
+            line.push_str(&format!(" ({})", error));
+        }
+        line.push_str(&format!(
+            ", attempts: {}, {}",
+            self.attempts,
+            ago(self.last_attempt.unwrap_or(self.created))
+        ));
+        if let (Status::Pending, Some(next)) = (self.status, self.next_attempt) {
+            line.push_str(&format!(", next in {}s", next.saturating_sub(now())));
+        }
+        line
+    }
+}
+
+/// Deliveries of the repository spool, the oldest first. Unreadable entries are skipped
+pub fn list(git_dir: &Path) -> DResult<Vec<Delivery>> {
+    let dir = spool_path(git_dir);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut result: Vec<Delivery> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "json") {
+            if let Ok(delivery) = serde_json::from_slice(&fs::read(path)?) {
+                result.push(delivery);
+            }
+        }
+    }
+    result.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(result)
+}
+
+pub fn print_log(repo: &Repository, failed_only: bool, format: Format) -> DResult<()> {
+    repo.exists()?;
+    let deliveries: Vec<Delivery> = list(repo.path())?
+        .into_iter()
+        .filter(|d| !failed_only || d.status != Status::Delivered)
+        .collect();
+    if !format.is_human() {
+        return output::print_list(format, &deliveries);
+    }
+    for d in deliveries {
+        println!("{}", d.summary());
+    }
+    Ok(())
+}
+
+/// Locks the spool until the file is dropped, so concurrent runs don't send the same delivery
+/// twice
+fn lock(git_dir: &Path) -> DResult<fs::File> {
+    let path = spool_path(git_dir).join(".lock");
+    let created = !path.exists();
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(&path)?;
+    if created {
+        chmod(&path, 0o664)?;
+    }
+    file.lock()?;
+    Ok(file)
+}
+
+/// Sends due pending deliveries of the spool (all pending ones if forced) and removes finished
+/// deliveries older than 30 days. Returns the number of delivered and still failing
+/// notifications
+pub fn deliver(
+    git_dir: &Path,
+    config: &RepoConfig,
+    name: &str,
+    force: bool,
+) -> DResult<(usize, usize)> {
+    if !spool_path(git_dir).exists() {
+        return Ok((0, 0));
+    }
+    let _lock = if dry_run() {
+        None
+    } else {
+        Some(lock(git_dir)?)
+    };
+    let now = now();
+    let mut delivered = 0;
+    let mut failed = 0;
+    for mut delivery in list(git_dir)? {
+        if delivery.status != Status::Pending {
+            if delivery.last_attempt.unwrap_or(delivery.created) + KEEP < now {
+                ops::remove_file(spool_path(git_dir).join(format!("{}.json", delivery.id)))?;
+            }
+            continue;
+        }
+        if !force && !delivery.is_due(now) {
+            continue;
+        }
+        if dry_run() {
+            ops::report(format!("resend {}", delivery.summary()));
+            continue;
+        }
+        delivery.attempt(config)?;
+        delivery.save(git_dir)?;
+        if delivery.status == Status::Delivered {
+            delivered += 1;
+        } else {
+            failed += 1;
+        }
+        println!("{}: {}", name.cyan().bold(), delivery.summary());
+    }
+    Ok((delivered, failed))
+}
+
+/// Resends due deliveries of the repository, see deliver
+pub fn retry(repo: &Repository, force: bool) -> DResult<(usize, usize)> {
+    deliver(repo.path(), &repo.read_config()?, repo.name(), force)
+}
+
+/// Resends due failed deliveries of the repositories (all if not specified) if retry = true,
+/// otherwise shows the numbers of pending and failed deliveries
+pub fn run(repos: Vec<Repository>, retry: bool, force: bool) -> DResult<()> {
+    let repos = if repos.is_empty() {
+        Repository::list()?
+    } else {
+        repos
+    };
+    let mut delivered = 0;
+    let mut failed = 0;
+    for repo in repos {
+        repo.exists()?;
+        if retry {
+            let (d, f) = self::retry(&repo, force)?;
+            delivered += d;
+            failed += f;
+            continue;
+        }
+        let deliveries = list(repo.path())?;
+        let count = |status| deliveries.iter().filter(|d| d.status == status).count();
+        let (pending, failed) = (count(Status::Pending), count(Status::Failed));
+        if pending > 0 || failed > 0 {
+            println!(
+                "{}: {} pending, {} failed",
+                repo.name_colored(),
+                pending.to_string().yellow(),
+                failed.to_string().red()
+            );
+        }
+    }
+    if retry && delivered + failed > 0 {
+        println!(
+            "{} delivered, {} failed",
+            delivered.to_string().green().bold(),
+            failed.to_string().red().bold()
+        );
+    }
+    Ok(())
+}
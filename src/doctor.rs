@@ -0,0 +1,331 @@
+use crate::config::config;
+use crate::hook;
+use crate::ops;
+use crate::output::{self, Format, Tabular};
+use crate::repo::Repository;
+use crate::system::system;
+use crate::user::User;
+use crate::{failed, DResult, ErrorKind};
+use colored::Colorize;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// An inconsistency between repositories, groups, user homes and cgit configs
+#[derive(Serialize)]
+#[serde(tag = "category", rename_all = "snake_case")]
+pub enum Problem {
+    /// A repository group without the repository
+    OrphanGroup { group: String },
+    /// A repository without its group (archived repositories are skipped)
+    MissingGroup { repo: String },
+    /// A symlink in the user's home which doesn't point to an accessible repository
+    BrokenLink { user: String, path: String },
+    /// The user has access to the repository but the home symlink is missing or wrong
+    MissingLink { user: String, repo: String },
+    /// A cgit config of a non-existing user
+    StaleCgitrc { path: String },
+    /// A maintainer entry (repository-wide or of a branch) of a user who has no access to the
+    /// repository
+    StaleMaintainer {
+        repo: String,
+        user: String,
+        branch: Option<String>,
+    },
+    /// The hook shims are missing or installed by another gmg binary
+    OutdatedHooks { repo: String },
+}
+
+const CATEGORIES: &[&str] = &[
+    "orphan_group",
+    "missing_group",
+    "broken_link",
+    "missing_link",
+    "stale_cgitrc",
+    "stale_maintainer",
+    "outdated_hooks",
+];
+
+impl Problem {
+    fn category(&self) -> &'static str {
+        match self {
+            Problem::OrphanGroup { .. } => "orphan_group",
+            Problem::MissingGroup { .. } => "missing_group",
+            Problem::BrokenLink { .. } => "broken_link",
+            Problem::MissingLink { .. } => "missing_link",
+            Problem::StaleCgitrc { .. } => "stale_cgitrc",
+            Problem::StaleMaintainer { .. } => "stale_maintainer",
+            Problem::OutdatedHooks { .. } => "outdated_hooks",
+        }
+    }
+    fn title(&self) -> &'static str {
+        match self {
+            Problem::OrphanGroup { .. } => "Groups without repositories",
+            Problem::MissingGroup { .. } => "Repositories without groups",
+            Problem::BrokenLink { .. } => "Broken home symlinks",
+            Problem::MissingLink { .. } => "Missing home symlinks",
+            Problem::StaleCgitrc { .. } => "Stale cgit configs",
+            Problem::StaleMaintainer { .. } => "Maintainers without access",
+            Problem::OutdatedHooks { .. } => "Outdated hooks",
+        }
+    }
+    fn object(&self) -> String {
+        match self {
+            Problem::OrphanGroup { group } => group.clone(),
+            Problem::MissingGroup { repo } | Problem::OutdatedHooks { repo } => repo.clone(),
+            Problem::BrokenLink { path, .. } | Problem::StaleCgitrc { path } => path.clone(),
+            Problem::MissingLink { user, repo }
+            | Problem::StaleMaintainer {
+                repo,
+                user,
+                branch: None,
+            } => format!("{}:{}", user, repo),
+            Problem::StaleMaintainer {
+                repo,
+                user,
+                branch: Some(branch),
+            } => format!("{}:{}:{}", user, repo, branch),
+        }
+    }
+    pub fn fix(&self) -> DResult<()> {
+        match self {
+            Problem::OrphanGroup { group } => system().group_del(group)?,
+            Problem::MissingGroup { repo } => {
+                let repo: Repository = repo.parse()?;
+                system().group_add(&repo.group()?)?;
+                repo.fix(false)?;
+            }
+            Problem::BrokenLink { user, path } => {
+                let user: User = user.parse()?;
+                let path = Path::new(path);
+                ops::remove_file(path)?;
+                if let Ok(rel) = path.strip_prefix(user.home()) {
+                    if rel.components().count() > 1 {
+                        if let Some(top) = rel.components().next() {
+                            ops::remove_empty_dirs(user.home().join(top));
+                        }
+                    }
+                }
+                user.update_cgit()?;
+            }
+            Problem::MissingLink { user, repo } => {
+                let repo: Repository = repo.parse()?;
+                let user: User = user.parse()?;
+                user.attach(&repo)?;
+                user.update_cgit()?;
+            }
+            Problem::StaleCgitrc { path } => ops::remove_file(path)?,
+            Problem::StaleMaintainer { repo, user, branch } => {
+                let user: User = user.parse()?;
+                if let Some(branch) = branch {
+                    user.maintainer_unset_branch(&repo.parse()?, branch)?;
+                } else {
+                    user.maintainer_unset(&repo.parse()?)?;
+                }
+            }
+            Problem::OutdatedHooks { repo } => repo.parse::<Repository>()?.fix(false)?,
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::OrphanGroup { group } => write!(f, "{}", group),
+            Problem::MissingGroup { repo } | Problem::OutdatedHooks { repo } => {
+                write!(f, "{}", repo.cyan().bold())
+            }
+            Problem::BrokenLink { user, path } => write!(f, "{}: {}", user.yellow(), path),
+            Problem::MissingLink { user, repo }
+            | Problem::StaleMaintainer {
+                repo,
+                user,
+                branch: None,
+            } => write!(f, "{}: {}", user.yellow(), repo.cyan().bold()),
+            Problem::StaleMaintainer {
+                repo,
+                user,
+                branch: Some(branch),
+            } => write!(f, "{}: {} ({})", user.yellow(), repo.cyan().bold(), branch),
+            Problem::StaleCgitrc { path } => write!(f, "{}", path),
+        }
+    }
+}
+
+impl Tabular for Problem {
+    fn header() -> &'static [&'static str] {
+        &["category", "object"]
+    }
+    fn row(&self) -> Vec<String> {
+        vec![self.category().to_owned(), self.object()]
+    }
+}
+
+/// Repositories archived by older versions are detected by permissions
+fn is_archived(repo: &Repository) -> bool {
+    repo.read_config().is_ok_and(|c| c.is_archived())
+        || fs::metadata(repo.path()).is_ok_and(|m| m.permissions().mode() & 0o777 == 0o700)
+}
+
+/// Collects symlinks in the directory, dot entries are skipped
+fn find_links(dir: &Path, result: &mut Vec<PathBuf>) -> DResult<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            result.push(entry.path());
+        } else if file_type.is_dir() {
+            find_links(&entry.path(), result)?;
+        }
+    }
+    Ok(())
+}
+
+/// Scans all repositories, groups, users and cgit configs
+pub fn scan() -> DResult<Vec<Problem>> {
+    let config = config();
+    let system = system();
+    let mut problems = Vec::new();
+    let repos = Repository::list()?;
+    let mut mapped = Vec::new();
+    for repo in &repos {
+        mapped.push((repo.group()?, repo));
+    }
+    let repo_groups: BTreeMap<&str, &Repository> =
+        mapped.iter().map(|(g, r)| (g.as_str(), *r)).collect();
+    for group in system.groups()? {
+        if group.starts_with(&config.group_prefix) && !repo_groups.contains_key(group.as_str()) {
+            problems.push(Problem::OrphanGroup { group });
+        }
+    }
+    for (group, repo) in &mapped {
+        if !system.group_exists(group)? {
+            if !is_archived(repo) {
+                problems.push(Problem::MissingGroup {
+                    repo: repo.name().to_owned(),
+                });
+            }
+            continue;
+        }
+        let members = system.group_members(group)?;
+        let config = repo.read_config()?;
+        for login in config.maintainers() {
+            if !members.contains(&login) {
+                problems.push(Problem::StaleMaintainer {
+                    repo: repo.name().to_owned(),
+                    user: login,
+                    branch: None,
+                });
+            }
+        }
+        for m in config.branch_maintainers() {
+            if !members.contains(&m.login) {
+                problems.push(Problem::StaleMaintainer {
+                    repo: repo.name().to_owned(),
+                    user: m.login,
+                    branch: Some(m.branch),
+                });
+            }
+        }
+        if !hook::is_installed(repo.path())? {
+            problems.push(Problem::OutdatedHooks {
+                repo: repo.name().to_owned(),
+            });
+        }
+    }
+    let mut logins = BTreeSet::new();
+    for (login, _) in system.users()? {
+        let user: User = login.parse()?;
+        let mut links = Vec::new();
+        if user.home().is_dir() {
+            find_links(user.home(), &mut links)?;
+        }
+        let repos: Vec<&Repository> = system
+            .user_groups(&login)?
+            .iter()
+            .filter_map(|g| repo_groups.get(g.as_str()).copied())
+            .collect();
+        for link in links {
+            let valid = repos.iter().any(|r| {
+                link == user.home().join(r.name())
+                    && fs::read_link(&link).is_ok_and(|target| target == r.path())
+            });
+            if !valid {
+                problems.push(Problem::BrokenLink {
+                    user: login.clone(),
+                    path: link.to_string_lossy().into_owned(),
+                });
+            }
+        }
+        for repo in repos {
+            let link = user.home().join(repo.name());
+            if !fs::read_link(&link).is_ok_and(|target| target == repo.path()) {
+                problems.push(Problem::MissingLink {
+                    user: login.clone(),
+                    repo: repo.name().to_owned(),
+                });
+            }
+        }
+        logins.insert(login);
+    }
+    let cgit_dir = config.git_path.join(".config/cgit");
+    if cgit_dir.is_dir() {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(cgit_dir)? {
+            let path = entry?.path();
+            if let Some(login) = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_suffix(".cgitrc"))
+            {
+                if !logins.contains(login) {
+                    paths.push(path.to_string_lossy().into_owned());
+                }
+            }
+        }
+        paths.sort();
+        problems.extend(paths.into_iter().map(|path| Problem::StaleCgitrc { path }));
+    }
+    problems.sort_by_key(|p| CATEGORIES.iter().position(|c| *c == p.category()));
+    Ok(problems)
+}
+
+/// Reports the problems found by category, with fix = true repairs them
+pub fn run(fix: bool, format: Format) -> DResult<()> {
+    let problems = scan()?;
+    if !format.is_human() {
+        output::print_list(format, &problems)?;
+    } else if problems.is_empty() {
+        println!("No problems found");
+    } else {
+        let mut category = "";
+        for problem in &problems {
+            if problem.category() != category {
+                category = problem.category();
+                println!("{}", problem.title().white().bold());
+            }
+            println!("  {}", problem);
+        }
+    }
+    if problems.is_empty() {
+        return Ok(());
+    }
+    if fix {
+        for problem in &problems {
+            problem.fix()?;
+        }
+        if format.is_human() {
+            println!("Problems fixed: {}", problems.len());
+        }
+        Ok(())
+    } else {
+        failed!(format!("Problems found: {}", problems.len()))
+    }
+}
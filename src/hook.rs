@@ -0,0 +1,471 @@
+use crate::audit::{self, PushRecord};
+use crate::cmd::quote;
+use crate::config::config;
+use crate::delivery::{self, Delivery, Kind};
+use crate::mail::Summary;
+use crate::ops::{self, chmod};
+use crate::repo_config::RepoConfig;
+use crate::time::now;
+use crate::webhook::Payload;
+use crate::{cmd, err, failed, DResult, ErrorKind};
+use colored::Colorize;
+use std::ffi::OsString;
+use std::fs;
+use std::io::BufRead;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+/// Hooks installed into repositories as shims which call gmg
+pub const HOOKS: &[&str] = &["update", "post-receive"];
+
+/// A ref update, as passed to git hooks
+pub struct RefUpdate {
+    pub refname: String,
+    pub oldrev: String,
+    pub newrev: String,
+}
+
+impl RefUpdate {
+    pub fn is_create(&self) -> bool {
+        is_zero(&self.oldrev)
+    }
+    pub fn is_delete(&self) -> bool {
+        is_zero(&self.newrev)
+    }
+}
+
+/// Parses pre-receive/post-receive input: "<oldrev> <newrev> <refname>" lines
+pub fn read_updates<R: BufRead>(input: R) -> DResult<Vec<RefUpdate>> {
+    let mut result = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let mut sp = line.split(' ');
+        match (sp.next(), sp.next(), sp.next(), sp.next()) {
+            (Some(oldrev), Some(newrev), Some(refname), None) => result.push(RefUpdate {
+                refname: refname.to_owned(),
+                oldrev: oldrev.to_owned(),
+                newrev: newrev.to_owned(),
+            }),
+            _ => return failed!(Parse, format!("invalid hook input: {}", line)),
+        }
+    }
+    Ok(result)
+}
+
+/// Checks if the revision is all-zero (a ref is created or deleted), for both SHA-1 and SHA-256
+fn is_zero(rev: &str) -> bool {
+    rev.chars().all(|c| c == '0')
+}
+
+/// Creates the directory (and missing parents) writable by the repository group, for data
+/// written by hooks
+pub fn create_shared_dir(dir: &Path) -> DResult<()> {
+    if dir.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = dir.parent() {
+        create_shared_dir(parent)?;
+    }
+    fs::create_dir(dir)?;
+    chmod(dir, 0o2775)?;
+    Ok(())
+}
+
+/// Gets the repository name from its path, the directory name if outside of the git path
+fn repo_name(git_dir: &Path) -> String {
+    let path = fs::canonicalize(git_dir).unwrap_or_else(|_| git_dir.to_owned());
+    let git_path =
+        fs::canonicalize(&config().git_path).unwrap_or_else(|_| config().git_path.clone());
+    let name = path
+        .strip_prefix(&git_path)
+        .unwrap_or_else(|_| path.file_name().map_or_else(|| Path::new(""), Path::new));
+    let name = name.to_string_lossy();
+    name.strip_suffix(".git").unwrap_or(&name).to_owned()
+}
+
+/// Access rules of a repository for the pushing user
+pub struct Hook {
+    git_dir: PathBuf,
+    /// repository name
+    name: String,
+    config: RepoConfig,
+    user: String,
+    root: bool,
+}
+
+impl Hook {
+    pub fn new(git_dir: &Path, user: &str, root: bool) -> DResult<Self> {
+        Ok(Self {
+            git_dir: git_dir.to_owned(),
+            name: repo_name(git_dir),
+            config: RepoConfig::load(&git_dir.join("config"))?,
+            user: user.to_owned(),
+            root,
+        })
+    }
+    /// Git runs hooks in the repository directory as the pushing user
+    pub fn from_env() -> DResult<Self> {
+        let git_dir = std::env::var_os("GIT_DIR").map_or_else(|| PathBuf::from("."), PathBuf::from);
+        let Some(user) = users::get_current_username() else {
+            return failed!("unable to get the current user".to_owned());
+        };
+        Self::new(
+            &git_dir,
+            &user.to_string_lossy(),
+            users::get_current_uid() == 0,
+        )
+    }
+    fn is_maintainer(&self) -> bool {
+        self.root || self.config.maintainers().contains(&self.user)
+    }
+    fn is_branch_maintainer(&self, branch: &str) -> bool {
+        self.root || self.config.is_branch_maintainer(&self.user, branch)
+    }
+    fn object_type(&self, rev: &str) -> DResult<String> {
+        if is_zero(rev) {
+            return Ok("delete".to_owned());
+        }
+        Ok(cmd!("git", "cat-file", "-t", rev)
+            .dir(&self.git_dir)
+            .run()?
+            .stdout
+            .trim()
+            .to_owned())
+    }
+    fn is_fast_forward(&self, oldrev: &str, newrev: &str) -> DResult<bool> {
+        Ok(cmd!("git", "rev-list", format!("{}..{}", newrev, oldrev))
+            .dir(&self.git_dir)
+            .run()?
+            .stdout
+            .trim()
+            .is_empty())
+    }
+    /// Checks the ref update with the read-only, branch and tag protection rules
+    pub fn check(&self, update: &RefUpdate) -> DResult<()> {
+        let RefUpdate {
+            refname,
+            oldrev,
+            newrev,
+        } = update;
+        if !self.root && self.config.read_only_users().contains(&self.user) {
+            return failed!(
+                Permission,
+                format!("User {} has read-only access to the repository", self.user)
+            );
+        }
+        let object_type = self.object_type(newrev)?;
+        if let Some(branch) = refname.strip_prefix("refs/heads/") {
+            let rule = self.config.protection_rule(branch);
+            if let Some(ref rule) = rule {
+                if !self.is_branch_maintainer(branch) {
+                    return failed!(
+                        Permission,
+                        format!(
+                            "User {} is not allowed to push into {} (protected by {})",
+                            self.user, branch, rule
+                        )
+                    );
+                }
+            }
+            match object_type.as_str() {
+                "commit" => {
+                    if rule.is_some()
+                        && !is_zero(oldrev)
+                        && !self.is_fast_forward(oldrev, newrev)?
+                    {
+                        return failed!(
+                            Permission,
+                            format!(
+                                "Non-fast-forward updates are not allowed for branch {}",
+                                branch
+                            )
+                        );
+                    }
+                    return Ok(());
+                }
+                "delete" => {
+                    if rule.is_some() {
+                        return failed!(
+                            Permission,
+                            format!("branch {} is protected can not be deleted", branch)
+                        );
+                    }
+                    return Ok(());
+                }
+                _ => {}
+            }
+        } else if let Some(tag) = refname.strip_prefix("refs/tags/") {
+            let rule = self.config.tag_protection_rule(tag);
+            match object_type.as_str() {
+                // un-annotated or annotated tag
+                "commit" | "tag" => {
+                    if let Some(rule) = rule {
+                        if !is_zero(oldrev) {
+                            return failed!(
+                                Permission,
+                                format!("tag {} is protected (by {}) can not be moved", tag, rule)
+                            );
+                        }
+                    }
+                    return Ok(());
+                }
+                "delete" => {
+                    if let Some(rule) = rule {
+                        if !self.is_maintainer() {
+                            return failed!(
+                                Permission,
+                                format!(
+                                    "User {} is not allowed to delete tag {} (protected by {})",
+                                    self.user, tag, rule
+                                )
+                            );
+                        }
+                    }
+                    return Ok(());
+                }
+                _ => {}
+            }
+        } else if refname.starts_with("refs/remotes/")
+            && (object_type == "commit" || object_type == "delete")
+        {
+            // tracking branch
+            return Ok(());
+        }
+        failed!(
+            Permission,
+            format!(
+                "Unknown type of update to ref {} of type {}",
+                refname, object_type
+            )
+        )
+    }
+    /// Checks if the update is non-fast-forward
+    fn is_forced(&self, update: &RefUpdate) -> bool {
+        !update.is_create()
+            && !update.is_delete()
+            && !self
+                .is_fast_forward(&update.oldrev, &update.newrev)
+                .unwrap_or(true)
+    }
+    /// Records the checked update in the push log, a failure is reported only
+    fn audit(&self, update: &RefUpdate, reason: Option<String>) {
+        let record = PushRecord {
+            timestamp: now(),
+            user: self.user.clone(),
+            refname: update.refname.clone(),
+            oldrev: update.oldrev.clone(),
+            newrev: update.newrev.clone(),
+            forced: self.is_forced(update),
+            accepted: reason.is_none(),
+            reason,
+        };
+        if let Err(e) = audit::append(&self.git_dir, &record) {
+            err!(format!("unable to write the push log: {}", e));
+        }
+    }
+    /// Checks the update and records it in the push log
+    pub fn update(&self, update: &RefUpdate) -> DResult<()> {
+        let result = self.check(update);
+        self.audit(update, result.as_ref().err().map(ToString::to_string));
+        result
+    }
+    /// Checks all the updates, the push is rejected if any of them is denied. All the updates
+    /// are recorded in the push log
+    pub fn pre_receive(&self, updates: &[RefUpdate]) -> DResult<()> {
+        let results: Vec<DResult<()>> = updates.iter().map(|u| self.check(u)).collect();
+        let rejected = results.iter().any(Result::is_err);
+        for (update, result) in updates.iter().zip(&results) {
+            let reason = match result {
+                Err(e) => Some(e.to_string()),
+                Ok(()) if rejected => Some("another ref update has been rejected".to_owned()),
+                Ok(()) => None,
+            };
+            self.audit(update, reason);
+        }
+        results.into_iter().collect()
+    }
+    /// Notifications of the update: the RCI job of the branch and the matching webhooks
+    pub fn deliveries(&self, update: &RefUpdate) -> DResult<Vec<Delivery>> {
+        let mut result = Vec::new();
+        if let Some(branch) = update.refname.strip_prefix("refs/heads/") {
+            if !update.is_delete() && self.config.rci().iter().any(|r| r.branch == branch) {
+                result.push(Delivery::new(
+                    Kind::Rci,
+                    branch,
+                    "push",
+                    Some(&update.refname),
+                    None,
+                ));
+            }
+        }
+        let webhooks: Vec<_> = self
+            .config
+            .webhooks()
+            .into_iter()
+            .filter(|w| w.matches(&update.refname))
+            .collect();
+        if !webhooks.is_empty() {
+            let payload = serde_json::to_value(Payload::push(
+                &self.git_dir,
+                &self.name,
+                update,
+                &self.user,
+            )?)?;
+            for w in webhooks {
+                result.push(Delivery::new(
+                    Kind::Webhook,
+                    &w.name,
+                    "push",
+                    Some(&update.refname),
+                    Some(payload.clone()),
+                ));
+            }
+        }
+        let recipients: Vec<_> = self
+            .config
+            .notifications()
+            .into_iter()
+            .filter(|n| n.matches(&update.refname))
+            .collect();
+        if !recipients.is_empty() && !update.is_delete() {
+            let summary = Summary::new(
+                &self.git_dir,
+                &self.name,
+                update,
+                &self.user,
+                self.is_forced(update),
+            )?;
+            for n in recipients {
+                result.push(Delivery::new(
+                    Kind::Email,
+                    &n.email,
+                    "push",
+                    Some(&update.refname),
+                    Some(summary.message(&n.email).into()),
+                ));
+            }
+        }
+        Ok(result)
+    }
+    /// Queues the notifications of the update as pending deliveries in the spool
+    pub fn queue(&self, update: &RefUpdate) -> DResult<Vec<Delivery>> {
+        let deliveries = self.deliveries(update)?;
+        for d in &deliveries {
+            d.save(&self.git_dir)?;
+        }
+        Ok(deliveries)
+    }
+    /// Starts gmg in the background to send the queued deliveries, so the push is not blocked
+    fn spawn_deliver(&self) -> DResult<()> {
+        let (exe, args) = gmg_command()?;
+        let mut command = std::process::Command::new(exe);
+        command
+            .args(args)
+            .args(["hook", "deliver"])
+            .current_dir(&self.git_dir)
+            .env("GIT_DIR", ".")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            // not killed when the client disconnects
+            .process_group(0);
+        // not waited for, the process is re-parented when the hook exits
+        command.spawn()?;
+        Ok(())
+    }
+}
+
+pub fn update(refname: &str, oldrev: &str, newrev: &str) -> DResult<()> {
+    Hook::from_env()?.update(&RefUpdate {
+        refname: refname.to_owned(),
+        oldrev: oldrev.to_owned(),
+        newrev: newrev.to_owned(),
+    })
+}
+
+pub fn pre_receive() -> DResult<()> {
+    Hook::from_env()?.pre_receive(&read_updates(std::io::stdin().lock())?)
+}
+
+/// Queues RCI triggers, webhooks and email notifications and sends them in the background. The
+/// refs are already updated so a failure is reported but does not stop notifications of other
+/// refs
+pub fn post_receive() -> DResult<()> {
+    let hook = Hook::from_env()?;
+    let mut queued = false;
+    for update in read_updates(std::io::stdin().lock())? {
+        match hook.queue(&update) {
+            Ok(deliveries) => queued |= !deliveries.is_empty(),
+            Err(e) => {
+                err!(e.to_string());
+            }
+        }
+    }
+    if queued {
+        hook.spawn_deliver()?;
+    }
+    Ok(())
+}
+
+/// Sends the deliveries queued by post-receive
+pub fn deliver() -> DResult<()> {
+    let hook = Hook::from_env()?;
+    delivery::deliver(&hook.git_dir, &hook.config, &hook.name, false)?;
+    Ok(())
+}
+
+/// The gmg binary and its arguments to pass the config if not the default one
+fn gmg_command() -> DResult<(PathBuf, Vec<OsString>)> {
+    let config = config();
+    let exe = match config.gmg_path {
+        Some(ref path) => path.clone(),
+        None => std::env::current_exe()?,
+    };
+    let mut args = Vec::new();
+    if let Some(ref source) = config.source {
+        args.push("-c".into());
+        args.push(source.into());
+    }
+    Ok((exe, args))
+}
+
+/// The shim script which calls gmg, the config is passed if not the default one
+pub fn shim(hook: &str) -> DResult<String> {
+    let (exe, args) = gmg_command()?;
+    let mut command = quote(&exe.to_string_lossy());
+    for arg in args {
+        command.push(' ');
+        command.push_str(&quote(&arg.to_string_lossy()));
+    }
+    Ok(format!(
+        "#!/bin/sh\n# installed by gmg, do not edit\nexec {} hook {} \"$@\"\n",
+        command, hook
+    ))
+}
+
+/// Installs the hook shims into the repository
+pub fn install(repo_path: &Path) -> DResult<()> {
+    let hooks_path = repo_path.join("hooks");
+    ops::create_dir_all(&hooks_path)?;
+    for hook in HOOKS {
+        let path = hooks_path.join(hook);
+        ops::write(&path, shim(hook)?)?;
+        chmod(&path, 0o755)?;
+    }
+    Ok(())
+}
+
+/// Checks if the current hook shims are installed
+pub fn is_installed(repo_path: &Path) -> DResult<bool> {
+    for hook in HOOKS {
+        let path = repo_path.join("hooks").join(hook);
+        if fs::read_to_string(path).ok() != Some(shim(hook)?) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,268 @@
+use crate::config::config;
+use crate::output::Tabular;
+use crate::{failed, DResult, ErrorKind};
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+/// A public SSH key, as an authorized_keys line
+#[derive(Clone)]
+pub struct PublicKey {
+    options: Option<String>,
+    key_type: String,
+    data: String,
+    comment: String,
+    bits: u32,
+    fingerprint: String,
+}
+
+#[derive(Serialize)]
+pub struct KeyInfo {
+    pub key_type: String,
+    pub bits: u32,
+    pub fingerprint: String,
+    pub comment: String,
+}
+
+impl Tabular for KeyInfo {
+    fn header() -> &'static [&'static str] {
+        &["key_type", "bits", "fingerprint", "comment"]
+    }
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.key_type.clone(),
+            self.bits.to_string(),
+            self.fingerprint.clone(),
+            self.comment.clone(),
+        ]
+    }
+}
+
+/// Reads SSH wire-format strings
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn string(&mut self) -> Option<&'a [u8]> {
+        let len = u32::from_be_bytes(self.data.get(..4)?.try_into().ok()?) as usize;
+        let value = self.data.get(4..4 + len)?;
+        self.data = &self.data[4 + len..];
+        Some(value)
+    }
+}
+
+fn mpint_bits(value: &[u8]) -> u32 {
+    let value: Vec<u8> = value.iter().copied().skip_while(|b| *b == 0).collect();
+    match value.first() {
+        Some(first) => (value.len() as u32 - 1) * 8 + (8 - first.leading_zeros()),
+        None => 0,
+    }
+}
+
+/// Checks the blob structure, returns the key size in bits
+fn parse_blob(key_type: &str, blob: &[u8]) -> Option<u32> {
+    let mut reader = Reader { data: blob };
+    if reader.string()? != key_type.as_bytes() {
+        return None;
+    }
+    let bits = match key_type {
+        "ssh-rsa" => {
+            reader.string()?;
+            mpint_bits(reader.string()?)
+        }
+        "ssh-dss" => {
+            let bits = mpint_bits(reader.string()?);
+            for _ in 0..3 {
+                reader.string()?;
+            }
+            bits
+        }
+        "ecdsa-sha2-nistp256" | "ecdsa-sha2-nistp384" | "ecdsa-sha2-nistp521" => {
+            let curve = reader.string()?;
+            if key_type.as_bytes().strip_prefix(b"ecdsa-sha2-") != Some(curve) {
+                return None;
+            }
+            reader.string()?;
+            key_type[key_type.len() - 3..].parse().ok()?
+        }
+        "sk-ecdsa-sha2-nistp256@openssh.com" => {
+            reader.string()?;
+            reader.string()?;
+            reader.string()?;
+            256
+        }
+        "ssh-ed25519" => {
+            if reader.string()?.len() != 32 {
+                return None;
+            }
+            256
+        }
+        "sk-ssh-ed25519@openssh.com" => {
+            if reader.string()?.len() != 32 {
+                return None;
+            }
+            reader.string()?;
+            256
+        }
+        _ => return None,
+    };
+    reader.data.is_empty().then_some(bits)
+}
+
+pub const KEY_TYPES: &[&str] = &[
+    "ssh-rsa",
+    "ssh-dss",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+    "ssh-ed25519",
+    "sk-ssh-ed25519@openssh.com",
+];
+
+/// Splits off the first field, double-quoted parts of options may contain spaces
+fn split_field(s: &str) -> (&str, &str) {
+    let mut quoted = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ' ' | '\t' if !quoted => return (&s[..i], s[i..].trim_start()),
+            _ => {}
+        }
+    }
+    (s, "")
+}
+
+impl FromStr for PublicKey {
+    type Err = ErrorKind;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let line = s.trim();
+        if line.starts_with("-----BEGIN") || line.starts_with("PuTTY-User-Key-File") {
+            return Err(ErrorKind::Parse(
+                "a private key has been provided, use the public one (.pub file)".to_owned(),
+            ));
+        }
+        let (mut key_type, mut rest) = split_field(line);
+        let mut options = None;
+        if !KEY_TYPES.contains(&key_type) {
+            let (t, r) = split_field(rest);
+            if !KEY_TYPES.contains(&t) {
+                return Err(ErrorKind::Parse(format!(
+                    "unsupported or missing key type: {}",
+                    key_type
+                )));
+            }
+            options = Some(key_type.to_owned());
+            (key_type, rest) = (t, r);
+        }
+        let (data, comment) = split_field(rest);
+        let blob = STANDARD
+            .decode(data)
+            .map_err(|e| ErrorKind::Parse(format!("invalid {} key data: {}", key_type, e)))?;
+        let bits = parse_blob(key_type, &blob).ok_or_else(|| {
+            ErrorKind::Parse(format!("malformed {} key: invalid key blob", key_type))
+        })?;
+        Ok(Self {
+            options,
+            key_type: key_type.to_owned(),
+            data: data.to_owned(),
+            comment: comment.to_owned(),
+            bits,
+            fingerprint: format!("SHA256:{}", STANDARD_NO_PAD.encode(Sha256::digest(&blob))),
+        })
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ref options) = self.options {
+            write!(f, "{} ", options)?;
+        }
+        write!(f, "{} {}", self.key_type, self.data)?;
+        if !self.comment.is_empty() {
+            write!(f, " {}", self.comment)?;
+        }
+        Ok(())
+    }
+}
+
+impl PublicKey {
+    pub fn comment(&self) -> &str {
+        &self.comment
+    }
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+    /// Checks if the key is identified by the SHA256 fingerprint, the base64 key data or the
+    /// full key line
+    pub fn is_identified_by(&self, id: &str) -> bool {
+        let id = id.trim();
+        id == self.fingerprint
+            || id == self.data
+            || id.parse::<PublicKey>().is_ok_and(|k| k.data == self.data)
+    }
+    /// Checks the key against the allowed types and the minimum RSA length of the config
+    pub fn check_policy(&self) -> DResult<()> {
+        let config = config();
+        if !config.key_types.contains(&self.key_type) {
+            return failed!(
+                Policy,
+                format!(
+                    "key {} rejected: type {} is not allowed (allowed: {})",
+                    self.fingerprint,
+                    self.key_type,
+                    config.key_types.join(", ")
+                )
+            );
+        }
+        if self.key_type == "ssh-rsa" && self.bits < config.min_rsa_bits {
+            return failed!(
+                Policy,
+                format!(
+                    "key {} rejected: RSA key is {} bits, at least {} required",
+                    self.fingerprint, self.bits, config.min_rsa_bits
+                )
+            );
+        }
+        Ok(())
+    }
+    pub fn info(&self) -> KeyInfo {
+        KeyInfo {
+            key_type: self.key_type.clone(),
+            bits: self.bits,
+            fingerprint: self.fingerprint.clone(),
+            comment: self.comment.clone(),
+        }
+    }
+}
+
+/// Parses keys, one per line, empty lines and comments are skipped. The keys are checked with
+/// the policy
+pub fn parse_keys(text: &str) -> DResult<Vec<PublicKey>> {
+    let mut result: Vec<PublicKey> = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let key: PublicKey = line
+            .parse()
+            .map_err(|e| ErrorKind::Parse(format!("line {}: {}", n + 1, e)))?;
+        key.check_policy()?;
+        if result.iter().any(|k| k.fingerprint == key.fingerprint) {
+            return failed!(
+                AlreadyExists,
+                format!("line {}: duplicate key {}", n + 1, key.fingerprint)
+            );
+        }
+        result.push(key);
+    }
+    if result.is_empty() {
+        return failed!(Parse, "no public keys found".to_owned());
+    }
+    Ok(result)
+}
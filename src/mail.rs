@@ -0,0 +1,217 @@
+use crate::config::config;
+use crate::hook::RefUpdate;
+use crate::webhook::{self, hex, Commit, Response};
+use crate::{cmd, DResult};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// Checks if the string looks like an email address
+pub fn valid_email(email: &str) -> bool {
+    let Some((local, domain)) = email.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !domain.contains('@')
+        && email
+            .chars()
+            .all(|c| c.is_ascii_graphic() && !"\"(),:;<>[\\]".contains(c))
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Encodes a header value as RFC 2047 if it is not ASCII
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_owned()
+    } else {
+        format!("=?utf-8?B?{}?=", STANDARD.encode(value))
+    }
+}
+
+fn short_rev(rev: &str) -> &str {
+    &rev[..rev.len().min(7)]
+}
+
+/// A push summary: commit subjects and the diffstat
+pub struct Summary {
+    pub repository: String,
+    pub refname: String,
+    pub oldrev: String,
+    pub newrev: String,
+    pub pusher: String,
+    pub forced: bool,
+    pub commits: Vec<Commit>,
+    pub diffstat: String,
+}
+
+impl Summary {
+    pub fn new(
+        git_dir: &Path,
+        repository: &str,
+        update: &RefUpdate,
+        pusher: &str,
+        forced: bool,
+    ) -> DResult<Self> {
+        let commits = webhook::commits(git_dir, update)?;
+        // the diff of a created ref starts from the parent of its first new commit
+        let base = if update.is_create() {
+            commits.first().and_then(|c| {
+                cmd!("git", "rev-parse", "--verify", "-q", format!("{}^", c.id))
+                    .dir(git_dir)
+                    .run()
+                    .ok()
+                    .map(|out| out.stdout.trim().to_owned())
+            })
+        } else {
+            Some(update.oldrev.clone())
+        };
+        let diffstat = match base {
+            Some(base) if !update.is_delete() => {
+                cmd!("git", "diff", "--stat=72", base, &update.newrev)
+                    .dir(git_dir)
+                    .run()?
+                    .stdout
+            }
+            _ => String::new(),
+        };
+        Ok(Self {
+            repository: repository.to_owned(),
+            refname: update.refname.clone(),
+            oldrev: update.oldrev.clone(),
+            newrev: update.newrev.clone(),
+            pusher: pusher.to_owned(),
+            forced,
+            commits,
+            diffstat,
+        })
+    }
+    fn ref_title(&self) -> String {
+        if let Some(branch) = self.refname.strip_prefix("refs/heads/") {
+            branch.to_owned()
+        } else if let Some(tag) = self.refname.strip_prefix("refs/tags/") {
+            format!("tag {}", tag)
+        } else {
+            self.refname.clone()
+        }
+    }
+    pub fn subject(&self) -> String {
+        format!(
+            "[{}] {} pushed {} commit{} to {}{}",
+            self.repository,
+            self.pusher,
+            self.commits.len(),
+            if self.commits.len() == 1 { "" } else { "s" },
+            self.ref_title(),
+            if self.forced { " (forced)" } else { "" }
+        )
+    }
+    pub fn text(&self) -> String {
+        let mut result = format!(
+            "{} pushed to {} of {}\n\n  {}..{}{}\n",
+            self.pusher,
+            self.ref_title(),
+            self.repository,
+            short_rev(&self.oldrev),
+            short_rev(&self.newrev),
+            if self.forced { " (forced)" } else { "" }
+        );
+        if !self.commits.is_empty() {
+            result.push_str("\nCommits:\n\n");
+            for c in &self.commits {
+                result.push_str(&format!(
+                    "  {} {} ({})\n",
+                    short_rev(&c.id),
+                    c.message,
+                    c.author
+                ));
+            }
+        }
+        if !self.diffstat.is_empty() {
+            result.push('\n');
+            result.push_str(&self.diffstat);
+        }
+        result
+    }
+    pub fn html(&self) -> String {
+        let mut result = format!(
+            "<html><body>\n<p><b>{}</b> pushed to <b>{}</b> of <b>{}</b></p>\n<p><code>{}..{}</code>{}</p>\n",
+            escape_html(&self.pusher),
+            escape_html(&self.ref_title()),
+            escape_html(&self.repository),
+            short_rev(&self.oldrev),
+            short_rev(&self.newrev),
+            if self.forced { " (forced)" } else { "" }
+        );
+        if !self.commits.is_empty() {
+            result.push_str("<ul>\n");
+            for c in &self.commits {
+                result.push_str(&format!(
+                    "<li><code>{}</code> {} <i>({})</i></li>\n",
+                    short_rev(&c.id),
+                    escape_html(&c.message),
+                    escape_html(&c.author)
+                ));
+            }
+            result.push_str("</ul>\n");
+        }
+        if !self.diffstat.is_empty() {
+            result.push_str(&format!("<pre>{}</pre>\n", escape_html(&self.diffstat)));
+        }
+        result.push_str("</body></html>\n");
+        result
+    }
+    /// The complete message (multipart/alternative, plain text and HTML)
+    pub fn message(&self, to: &str) -> String {
+        let boundary = format!(
+            "gmg-{}",
+            hex(&Sha256::digest(format!("{}:{}", self.newrev, to).as_bytes())[..8])
+        );
+        let mut result = String::new();
+        if let Some(ref from) = config().mail_from {
+            result.push_str(&format!("From: {}\n", from));
+        }
+        result.push_str(&format!(
+            "To: {}\nSubject: {}\nMIME-Version: 1.0\n\
+            Content-Type: multipart/alternative; boundary=\"{}\"\n\
+            X-Gmg-Repository: {}\nX-Gmg-Ref: {}\n\n",
+            to,
+            encode_header(&self.subject()),
+            boundary,
+            self.repository,
+            self.refname
+        ));
+        for (content_type, body) in [("text/plain", self.text()), ("text/html", self.html())] {
+            result.push_str(&format!(
+                "--{}\nContent-Type: {}; charset=utf-8\nContent-Transfer-Encoding: 8bit\n\n{}\n",
+                boundary, content_type, body
+            ));
+        }
+        result.push_str(&format!("--{}--\n", boundary));
+        result
+    }
+}
+
+/// Pipes the message to sendmail
+pub fn send(to: &str, message: &str) -> DResult<Response> {
+    let out = cmd!(&config().sendmail, "-i", "--", to)
+        .input(message)
+        .run_any()?;
+    let error = if out.success() {
+        None
+    } else if out.stderr.trim().is_empty() {
+        Some(format!("sendmail exit code {}", out.code))
+    } else {
+        Some(out.stderr.trim().to_owned())
+    };
+    Ok(Response { code: None, error })
+}
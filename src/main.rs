@@ -2,17 +2,42 @@
 use bmart_derive::EnumStr;
 use clap::{Parser, Subcommand};
 use colored::Colorize;
-use lazy_static::lazy_static;
 use std::fmt;
-use std::fs;
-use std::os::unix::fs::PermissionsExt;
-use std::path::{Path, PathBuf};
-use std::sync::atomic;
-
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::{atomic, Arc};
+
+mod audit;
+mod backup;
+mod cmd;
+mod config;
+mod delivery;
+mod doctor;
+mod hook;
+mod key;
+mod mail;
+mod namespace;
+mod ops;
+mod output;
 mod repo;
+mod repo_config;
+mod state;
+mod system;
+mod team;
+#[cfg(test)]
+mod tests;
+mod time;
+mod trash;
 mod user;
+mod webhook;
+
+use config::{config, Config};
+use output::Format;
 
+use namespace::Namespace;
 use repo::Repository;
+use state::State;
+use team::Team;
 use user::User;
 
 impl std::error::Error for ErrorKind {}
@@ -20,14 +45,13 @@ impl std::error::Error for ErrorKind {}
 const AUTHOR: &str = "Serhij S. / Bohemia Automation";
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-const GROUP_PFX: &str = "g_";
-
-const GIT_USER: &str = "git";
-
-const MAIN_BRANCH: &str = "main";
-const PROTECTED_BRANCHES: [&str; 1] = [MAIN_BRANCH];
-
 static VERBOSE: atomic::AtomicBool = atomic::AtomicBool::new(false);
+static DRY_RUN: atomic::AtomicBool = atomic::AtomicBool::new(false);
+
+#[inline]
+pub fn dry_run() -> bool {
+    DRY_RUN.load(atomic::Ordering::SeqCst)
+}
 
 type DResult<T> = Result<T, Box<dyn std::error::Error>>;
 
@@ -36,6 +60,9 @@ macro_rules! failed {
     ($err: expr) => {
         Err(Box::new(ErrorKind::Failed($err)))
     };
+    ($kind: ident, $err: expr) => {
+        Err(Box::new(ErrorKind::$kind($err)))
+    };
 }
 
 #[macro_export]
@@ -45,63 +72,91 @@ macro_rules! err {
     };
 }
 
-#[inline]
-fn sh(cmd: &str) -> DResult<String> {
-    sh_cmd(cmd, true)
-}
-
-#[inline]
-fn sh_any(cmd: &str) -> DResult<String> {
-    sh_cmd(cmd, false)
+#[derive(Parser)]
+struct RepoRenameParams {
+    #[clap()]
+    repository: Repository,
+    #[clap()]
+    new_repository: Repository,
 }
 
-fn sh_cmd(cmd: &str, check_exit_code: bool) -> DResult<String> {
-    if VERBOSE.load(atomic::Ordering::SeqCst) {
-        println!("> {}", cmd.dimmed().bold());
-    }
-    let output = std::process::Command::new("sh")
-        .args(["-c", cmd])
-        .output()?;
-    let code = output.status.code().unwrap_or(-1);
-    let out = std::str::from_utf8(&output.stdout)?;
-    if check_exit_code && code != 0 {
-        eprintln!("{}", std::str::from_utf8(&output.stderr)?.red());
-        failed!(format!("{}\nprocess exit code: {}", out, code))
-    } else {
-        Ok(out.to_owned())
-    }
+#[derive(Parser)]
+struct RepoBranchesParams {
+    #[clap()]
+    repository: Repository,
+    #[clap(short = 's', long = "short")]
+    short: bool,
 }
 
-pub fn chmod<P: AsRef<Path>>(path: P, permissions: u32) -> DResult<()> {
-    fs::set_permissions(path, fs::Permissions::from_mode(permissions))?;
-    Ok(())
+#[derive(Parser)]
+struct RepoParams {
+    #[clap()]
+    repository: Repository,
 }
 
-lazy_static! {
-    static ref GIT_PATH: PathBuf = Path::new("/git").to_owned();
-    static ref HOME_PATH: PathBuf = Path::new("/home").to_owned();
+#[derive(Parser)]
+struct RepoListParams {
+    #[clap(short = 's', long = "short")]
+    short: bool,
+    #[clap(long = "archived", help = "Archived repositories only")]
+    archived: bool,
+    #[clap(
+        long = "active",
+        conflicts_with = "archived",
+        help = "Active (not archived) repositories only"
+    )]
+    active: bool,
 }
 
 #[derive(Parser)]
-struct RepoRenameParams {
+struct RepoLogParams {
     #[clap()]
     repository: Repository,
-    #[clap()]
-    new_repository: Repository,
+    #[clap(long = "user")]
+    user: Option<String>,
+    #[clap(
+        long = "ref",
+        help = "Branch name, full ref name (refs/...) or glob pattern"
+    )]
+    refname: Option<String>,
+    #[clap(
+        long = "since",
+        help = "Duration (30m, 12h, 7d, 2w), UTC date (YYYY-MM-DD [HH:MM[:SS]]) or timestamp"
+    )]
+    since: Option<String>,
+    #[clap(long = "rejected", help = "Show rejected updates only")]
+    rejected: bool,
 }
 
 #[derive(Parser)]
-struct RepoBranchesParams {
+struct RepoRestoreParams {
     #[clap()]
     repository: Repository,
-    #[clap(short = 's', long = "short")]
-    short: bool,
+    #[clap(long = "id", help = "Trash entry id, the latest one if not specified")]
+    id: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum TrashCommand {
+    List,
+    Purge(TrashPurgeParams),
 }
 
 #[derive(Parser)]
-struct RepoParams {
+struct TrashPurgeParams {
+    #[clap(
+        long = "older-than",
+        help = "Duration (30m, 12h, 7d, 2w), UTC date (YYYY-MM-DD [HH:MM[:SS]]) or timestamp, all entries if not specified"
+    )]
+    older_than: Option<String>,
+}
+
+#[derive(Parser)]
+struct RepoHooksLogParams {
     #[clap()]
     repository: Repository,
+    #[clap(long = "failed", help = "Show failed deliveries only")]
+    failed: bool,
 }
 
 #[derive(Parser)]
@@ -138,6 +193,24 @@ struct RepoBranchParams {
     branch: String,
 }
 
+#[derive(Parser)]
+struct RepoTagsParams {
+    #[clap()]
+    repository: Repository,
+    #[clap(help = "Tag name or glob pattern, e.g. v*")]
+    pattern: String,
+}
+
+#[derive(Parser)]
+struct RepoProtectionParams {
+    #[clap()]
+    repository: Repository,
+    #[clap(help = "Branch or tag name")]
+    name: String,
+    #[clap(long = "tag", help = "Check tag protection rules")]
+    tag: bool,
+}
+
 #[derive(Parser)]
 struct RepoCreateParams {
     #[clap()]
@@ -169,6 +242,27 @@ enum RepoProp {
 struct Args {
     #[clap(short = 'v', long = "verbose")]
     verbose: bool,
+    #[clap(
+        short = 'o',
+        long = "output",
+        default_value = "human",
+        help = "Output format: human, json, yaml or tsv"
+    )]
+    output: Format,
+    #[clap(
+        short = 'n',
+        long = "dry-run",
+        help = "Print the planned changes without applying them"
+    )]
+    dry_run: bool,
+    // processed before the args are parsed, see config::path_from_args
+    #[allow(dead_code)]
+    #[clap(
+        short = 'c',
+        long = "config",
+        help = "Config file (default: /etc/gmg/gmg.conf, env: GMG_CONFIG)"
+    )]
+    config: Option<PathBuf>,
     #[clap(subcommand)]
     command: Command,
 }
@@ -184,29 +278,221 @@ enum RepoCommand {
     Fix(RepoParams),
     Set(RepoSetParams),
     Info(RepoParams),
-    List(ListParams),
+    List(RepoListParams),
+    Log(RepoLogParams),
+    MigrateGroups,
     Protect(RepoBranchParams),
+    ProtectTags(RepoTagsParams),
+    Protection(RepoProtectionParams),
     Rename(RepoRenameParams),
+    Restore(RepoRestoreParams),
+    #[clap(subcommand)]
+    Trash(TrashCommand),
+    Unarchive(RepoParams),
     Unprotect(RepoBranchParams),
+    UnprotectTags(RepoTagsParams),
     Users(RepoParams),
+    HooksLog(RepoHooksLogParams),
     Rci(RepoRciParams),
+    #[clap(subcommand)]
+    Webhook(WebhookCommand),
+    #[clap(subcommand)]
+    Notify(NotifyCommand),
+}
+
+#[derive(Subcommand)]
+enum NotifyCommand {
+    Add(NotifyParams),
+    Remove(NotifyParams),
+    List(RepoParams),
+}
+
+#[derive(Parser)]
+struct NotifyParams {
+    #[clap()]
+    repository: Repository,
+    #[clap()]
+    email: String,
+    #[clap(
+        short = 'b',
+        long = "branch",
+        help = "Branch name or glob pattern, all branches if not specified"
+    )]
+    branches: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum WebhookCommand {
+    Add(WebhookAddParams),
+    Remove(WebhookParams),
+    List(RepoParams),
+    Test(WebhookParams),
+}
+
+#[derive(Parser)]
+struct WebhookAddParams {
+    #[clap()]
+    repository: Repository,
+    #[clap()]
+    name: String,
+    #[clap(help = "HTTP(S) endpoint")]
+    url: String,
+    #[clap(long = "secret", help = "Sign payloads with HMAC-SHA256")]
+    secret: Option<String>,
+    #[clap(
+        short = 'r',
+        long = "ref",
+        help = "Branch name, full ref name (refs/...) or glob pattern, all refs if not specified"
+    )]
+    refs: Vec<String>,
+    #[clap(long = "timeout", help = "Delivery timeout, in seconds (default: 10)")]
+    timeout: Option<u32>,
+}
+
+#[derive(Parser)]
+struct WebhookParams {
+    #[clap()]
+    repository: Repository,
+    #[clap()]
+    name: String,
 }
 
 #[derive(Subcommand)]
 enum UserCommand {
     Create(UserCreateParams),
     Destroy(UserParams),
-    Grant(UserRepoParams),
+    Grant(UserGrantParams),
     List(ListParams),
     Repos(UserReposParams),
     Revoke(UserRepoParams),
     Update(UserParams),
+    #[clap(subcommand)]
+    Key(UserKeyCommand),
+}
+
+#[derive(Subcommand)]
+enum UserKeyCommand {
+    Add(UserKeyAddParams),
+    Remove(UserKeyRemoveParams),
+    List(UserParams),
+}
+
+#[derive(Parser)]
+struct UserKeyAddParams {
+    #[clap(name = "login")]
+    user: User,
+    #[clap(help = "SSH public key file, '-' for stdin")]
+    key_file: String,
+}
+
+#[derive(Parser)]
+struct UserKeyRemoveParams {
+    #[clap(name = "login")]
+    user: User,
+    #[clap(help = "Key SHA256 fingerprint, key data or comment")]
+    key: String,
+}
+
+#[derive(Subcommand)]
+enum TeamCommand {
+    Create(TeamParams),
+    Destroy(TeamParams),
+    Add(TeamUserParams),
+    Remove(TeamUserParams),
+    List(ListParams),
+    Grant(TeamRepoParams),
+    Revoke(TeamRepoParams),
+}
+
+#[derive(Parser)]
+struct TeamParams {
+    #[clap()]
+    team: Team,
+}
+
+#[derive(Parser)]
+struct TeamUserParams {
+    #[clap()]
+    team: Team,
+    #[clap(name = "login")]
+    user: User,
+}
+
+#[derive(Parser)]
+struct TeamRepoParams {
+    #[clap()]
+    team: Team,
+    #[clap()]
+    repository: Repository,
+}
+
+#[derive(Subcommand)]
+enum NamespaceCommand {
+    Grant(NamespaceGrantParams),
+    Revoke(NamespaceUserParams),
+    List(ListParams),
+}
+
+#[derive(Parser)]
+struct NamespaceGrantParams {
+    #[clap(help = "Repository name prefix, e.g. backend/")]
+    namespace: Namespace,
+    #[clap(name = "login")]
+    user: User,
+    #[clap(long = "maintainer", help = "Grant the maintainer role")]
+    maintainer: bool,
+}
+
+#[derive(Parser)]
+struct NamespaceUserParams {
+    #[clap()]
+    namespace: Namespace,
+    #[clap(name = "login")]
+    user: User,
+}
+
+#[derive(Subcommand)]
+enum HookCommand {
+    Update(HookUpdateParams),
+    PreReceive,
+    PostReceive,
+    Deliver,
+}
+
+#[derive(Parser)]
+struct HookUpdateParams {
+    #[clap()]
+    refname: String,
+    #[clap()]
+    oldrev: String,
+    #[clap()]
+    newrev: String,
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    Show,
 }
 
 #[derive(Subcommand)]
 enum MaintainerCommand {
-    Set(UserRepoParams),
-    Unset(UserRepoParams),
+    Set(MaintainerParams),
+    Unset(MaintainerParams),
+    List(RepoParams),
+}
+
+#[derive(Parser)]
+struct MaintainerParams {
+    #[clap(name = "login")]
+    user: User,
+    #[clap()]
+    repository: Repository,
+    #[clap(
+        short = 'b',
+        long = "branch",
+        help = "Branch name or glob pattern, repository-wide if not specified"
+    )]
+    branches: Vec<String>,
 }
 
 #[derive(Parser)]
@@ -239,6 +525,16 @@ struct UserReposParams {
     short: bool,
 }
 
+#[derive(Parser)]
+struct UserGrantParams {
+    #[clap(name = "login")]
+    user: User,
+    #[clap()]
+    repository: Repository,
+    #[clap(long = "read-only", help = "Allow clone/fetch only")]
+    read_only: bool,
+}
+
 #[derive(Parser)]
 struct UserRepoParams {
     #[clap(name = "login")]
@@ -247,6 +543,50 @@ struct UserRepoParams {
     repository: Repository,
 }
 
+#[derive(Parser)]
+struct DeliverParams {
+    #[clap(long = "retry", help = "Resend failed deliveries which are due")]
+    retry: bool,
+    #[clap(long = "force", help = "Ignore the retry backoff")]
+    force: bool,
+    #[clap(help = "Repositories, all if not specified")]
+    repositories: Vec<Repository>,
+}
+
+#[derive(Parser)]
+struct BackupParams {
+    #[clap(help = "Destination directory")]
+    dir: PathBuf,
+    #[clap(help = "Repositories, all if not specified")]
+    repositories: Vec<Repository>,
+}
+
+#[derive(Parser)]
+struct RestoreParams {
+    #[clap(help = "Bundle file, the metadata file (.json) is read from the same directory")]
+    bundle: PathBuf,
+    #[clap(long = "name", help = "Restore under another name")]
+    name: Option<Repository>,
+}
+
+#[derive(Parser)]
+struct DoctorParams {
+    #[clap(long = "fix", help = "Repair the problems found")]
+    fix: bool,
+}
+
+#[derive(Parser)]
+struct ExportStateParams {
+    #[clap(long = "with-secrets", help = "Include RCI secrets")]
+    with_secrets: bool,
+}
+
+#[derive(Parser)]
+struct StateParams {
+    #[clap(help = "State file (TOML)")]
+    file: PathBuf,
+}
+
 #[derive(Subcommand)]
 enum Command {
     #[clap(subcommand)]
@@ -255,33 +595,160 @@ enum Command {
     User(UserCommand),
     #[clap(subcommand)]
     Maintainer(MaintainerCommand),
+    #[clap(subcommand)]
+    Team(TeamCommand),
+    #[clap(subcommand)]
+    Namespace(NamespaceCommand),
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+    Plan(StateParams),
+    Apply(StateParams),
+    ExportState(ExportStateParams),
+    Doctor(DoctorParams),
+    Deliver(DeliverParams),
+    Backup(BackupParams),
+    Restore(RestoreParams),
+    #[clap(subcommand, hide = true)]
+    Hook(HookCommand),
 }
 
 #[derive(Debug)]
 pub enum ErrorKind {
     Failed(String),
+    NotFound(String),
+    AlreadyExists(String),
+    InvalidName(String),
+    Permission(String),
+    Exec {
+        command: String,
+        code: i32,
+        stderr: String,
+    },
+    Config(String),
+    Parse(String),
+    Policy(String),
+}
+
+// exit code 2 is used by clap for invalid command-line arguments
+const EXIT_FAILED: u8 = 1;
+const EXIT_NOT_FOUND: u8 = 3;
+const EXIT_ALREADY_EXISTS: u8 = 4;
+const EXIT_INVALID_NAME: u8 = 5;
+const EXIT_PERMISSION: u8 = 6;
+const EXIT_EXEC: u8 = 7;
+const EXIT_CONFIG: u8 = 8;
+const EXIT_PARSE: u8 = 9;
+const EXIT_POLICY: u8 = 10;
+
+impl ErrorKind {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ErrorKind::Failed(_) => "failed",
+            ErrorKind::NotFound(_) => "not_found",
+            ErrorKind::AlreadyExists(_) => "already_exists",
+            ErrorKind::InvalidName(_) => "invalid_name",
+            ErrorKind::Permission(_) => "permission",
+            ErrorKind::Exec { .. } => "exec",
+            ErrorKind::Config(_) => "config",
+            ErrorKind::Parse(_) => "parse",
+            ErrorKind::Policy(_) => "policy",
+        }
+    }
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            ErrorKind::Failed(_) => EXIT_FAILED,
+            ErrorKind::NotFound(_) => EXIT_NOT_FOUND,
+            ErrorKind::AlreadyExists(_) => EXIT_ALREADY_EXISTS,
+            ErrorKind::InvalidName(_) => EXIT_INVALID_NAME,
+            ErrorKind::Permission(_) => EXIT_PERMISSION,
+            ErrorKind::Exec { .. } => EXIT_EXEC,
+            ErrorKind::Config(_) => EXIT_CONFIG,
+            ErrorKind::Parse(_) => EXIT_PARSE,
+            ErrorKind::Policy(_) => EXIT_POLICY,
+        }
+    }
+    /// Converts a boxed error to ErrorKind, I/O and parse errors are classified
+    pub fn from_boxed(err: Box<dyn std::error::Error>) -> Self {
+        let err = match err.downcast::<ErrorKind>() {
+            Ok(e) => return *e,
+            Err(e) => e,
+        };
+        let err = match err.downcast::<std::io::Error>() {
+            Ok(e) => {
+                return match e.kind() {
+                    std::io::ErrorKind::PermissionDenied => ErrorKind::Permission(e.to_string()),
+                    std::io::ErrorKind::NotFound => ErrorKind::NotFound(e.to_string()),
+                    _ => ErrorKind::Failed(e.to_string()),
+                }
+            }
+            Err(e) => e,
+        };
+        if err.is::<serde_json::Error>()
+            || err.is::<serde_yaml::Error>()
+            || err.is::<std::str::Utf8Error>()
+            || err.is::<std::string::FromUtf8Error>()
+        {
+            ErrorKind::Parse(err.to_string())
+        } else {
+            ErrorKind::Failed(err.to_string())
+        }
+    }
+    /// Prints the error to stderr, as JSON object if JSON output is selected
+    fn report(&self, format: Format) {
+        if format == Format::Json {
+            let mut value = serde_json::json!({
+                "error": self.kind(),
+                "message": self.to_string(),
+                "exit_code": self.exit_code(),
+            });
+            if let ErrorKind::Exec {
+                command,
+                code,
+                stderr,
+            } = self
+            {
+                value["command"] = command.as_str().into();
+                value["command_exit_code"] = (*code).into();
+                value["stderr"] = stderr.as_str().into();
+            }
+            eprintln!("{}", value);
+        } else {
+            err!(self.to_string());
+        }
+    }
 }
 
 impl fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                ErrorKind::Failed(msg) => msg,
-            }
-        )
+        match self {
+            ErrorKind::Failed(msg)
+            | ErrorKind::NotFound(msg)
+            | ErrorKind::AlreadyExists(msg)
+            | ErrorKind::InvalidName(msg)
+            | ErrorKind::Permission(msg)
+            | ErrorKind::Config(msg)
+            | ErrorKind::Parse(msg)
+            | ErrorKind::Policy(msg) => write!(f, "{}", msg),
+            ErrorKind::Exec {
+                command,
+                code,
+                stderr,
+            } => write!(
+                f,
+                "command failed: {}\nprocess exit code: {}\n{}",
+                command,
+                code,
+                stderr.trim_end()
+            ),
+        }
     }
 }
 
-fn repo_cmd(command: RepoCommand) -> DResult<()> {
+fn repo_cmd(command: RepoCommand, format: Format) -> DResult<()> {
     match command {
         RepoCommand::Archive(params) => params.repository.archive()?,
-        RepoCommand::Branches(params) => {
-            for r in params.repository.branches()? {
-                println!("{}", r.yellow());
-            }
-        }
+        RepoCommand::Unarchive(params) => params.repository.unarchive()?,
+        RepoCommand::Branches(params) => params.repository.print_branches(format)?,
         RepoCommand::Check(params) => params.repository.check()?,
         RepoCommand::Cleanup(params) => params.repository.cleanup()?,
         RepoCommand::Create(params) => {
@@ -294,8 +761,16 @@ fn repo_cmd(command: RepoCommand) -> DResult<()> {
         },
         RepoCommand::Destroy(params) => params.repository.destroy()?,
         RepoCommand::Fix(params) => params.repository.fix(true)?,
-        RepoCommand::Info(params) => params.repository.print_info()?,
-        RepoCommand::List(params) => Repository::print_all(params.short)?,
+        RepoCommand::Info(params) => params.repository.print_info(format)?,
+        RepoCommand::List(params) => {
+            let archived = match (params.archived, params.active) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            };
+            Repository::print_all(params.short, archived, format)?;
+        }
+        RepoCommand::MigrateGroups => Repository::migrate_groups()?,
         RepoCommand::Protect(params) => {
             params.repository.protect(&params.branch)?;
             println!(
@@ -305,6 +780,29 @@ fn repo_cmd(command: RepoCommand) -> DResult<()> {
                 "protected".green().bold()
             );
         }
+        RepoCommand::ProtectTags(params) => {
+            params.repository.protect_tags(&params.pattern)?;
+            println!(
+                "Repository {} tags {} have been {}",
+                params.repository.name_colored(),
+                params.pattern.yellow(),
+                "protected".green().bold()
+            );
+        }
+        RepoCommand::UnprotectTags(params) => {
+            params.repository.unprotect_tags(&params.pattern)?;
+            println!(
+                "Repository {} tags {} have been {}",
+                params.repository.name_colored(),
+                params.pattern.yellow(),
+                "unprotected".red().bold()
+            );
+        }
+        RepoCommand::Protection(params) => {
+            params
+                .repository
+                .print_protection(&params.name, params.tag, format)?;
+        }
         RepoCommand::Rename(params) => params.repository.rename(&params.new_repository)?,
         RepoCommand::Unprotect(params) => {
             params.repository.unprotect(&params.branch)?;
@@ -315,10 +813,31 @@ fn repo_cmd(command: RepoCommand) -> DResult<()> {
                 "unprotected".red().bold()
             );
         }
-        RepoCommand::Users(params) => {
-            for user in params.repository.users()? {
-                println!("{}", user.login_colored());
-            }
+        RepoCommand::Users(params) => params.repository.print_users(format)?,
+        RepoCommand::Restore(params) => {
+            trash::restore(&params.repository, params.id.as_deref())?;
+        }
+        RepoCommand::Trash(c) => match c {
+            TrashCommand::List => trash::print(format)?,
+            TrashCommand::Purge(params) => trash::purge(
+                params
+                    .older_than
+                    .as_deref()
+                    .map(time::parse_time)
+                    .transpose()?,
+            )?,
+        },
+        RepoCommand::Log(params) => {
+            let filter = audit::Filter {
+                user: params.user,
+                refname: params.refname,
+                since: params.since.as_deref().map(time::parse_time).transpose()?,
+                rejected: params.rejected,
+            };
+            audit::print(&params.repository, &filter, format)?;
+        }
+        RepoCommand::HooksLog(params) => {
+            delivery::print_log(&params.repository, params.failed, format)?;
         }
         RepoCommand::Rci(params) => {
             let branch = params.branch;
@@ -333,78 +852,163 @@ fn repo_cmd(command: RepoCommand) -> DResult<()> {
                     let rci_trigger_url = format!("{url}/job/{rci_job}/trigger");
                     params
                         .repository
-                        .set(&format!("hooks.branch.{branch}.rci.url"), &rci_trigger_url)?;
-                    params
-                        .repository
-                        .set(&format!("hooks.branch.{branch}.rci.secret"), &rci_secret)?;
-                    println!(
-                        "RCI config {} for {} branch {}, trigger URL: {}",
-                        "SET".green().bold(),
-                        params.repository.name_colored(),
-                        branch.yellow(),
-                        rci_trigger_url
-                    );
-                }
-                RciCommand::Unset => {
-                    params
-                        .repository
-                        .unset(&format!("hooks.branch.{branch}.rci.url"))?;
-                    params
-                        .repository
-                        .unset(&format!("hooks.branch.{branch}.rci.secret"))?;
-                    println!(
-                        "RCI config {} for {} branch {}",
-                        "UNSET".yellow().bold(),
-                        params.repository.name_colored(),
-                        branch.yellow()
-                    );
+                        .rci_set(&branch, &rci_trigger_url, &rci_secret)?;
                 }
+                RciCommand::Unset => params.repository.rci_unset(&branch)?,
             }
         }
+        RepoCommand::Webhook(c) => match c {
+            WebhookCommand::Add(params) => params.repository.webhook_add(
+                &params.name,
+                &params.url,
+                params.secret.as_deref(),
+                &params.refs,
+                params.timeout,
+            )?,
+            WebhookCommand::Remove(params) => params.repository.webhook_remove(&params.name)?,
+            WebhookCommand::List(params) => params.repository.print_webhooks(format)?,
+            WebhookCommand::Test(params) => params.repository.webhook_test(&params.name)?,
+        },
+        RepoCommand::Notify(c) => match c {
+            NotifyCommand::Add(params) => params
+                .repository
+                .notify_add(&params.email, &params.branches)?,
+            NotifyCommand::Remove(params) => params
+                .repository
+                .notify_remove(&params.email, &params.branches)?,
+            NotifyCommand::List(params) => params.repository.print_notifications(format)?,
+        },
     }
     Ok(())
 }
 
-fn user_cmd(command: UserCommand) -> DResult<()> {
+fn user_cmd(command: UserCommand, format: Format) -> DResult<()> {
     match command {
         UserCommand::Create(params) => params.user.create(&params.name, &params.key_file)?,
         UserCommand::Destroy(params) => params.user.destroy()?,
-        UserCommand::Grant(params) => params.user.grant(&params.repository)?,
-        UserCommand::List(params) => User::print_all(params.short)?,
-        UserCommand::Repos(params) => {
-            for r in params.user.repos()? {
-                if params.short {
-                    println!("{}", r.name_colored(),);
-                } else {
-                    println!(
-                        "{} ({})",
-                        r.name_colored(),
-                        r.read_description()?.unwrap_or_default()
-                    );
-                }
-            }
-        }
+        UserCommand::Grant(params) => params.user.grant(&params.repository, params.read_only)?,
+        UserCommand::List(params) => User::print_all(params.short, format)?,
+        UserCommand::Repos(params) => params.user.print_repos(params.short, format)?,
         UserCommand::Revoke(params) => params.user.revoke(&params.repository)?,
         UserCommand::Update(params) => params.user.update()?,
+        UserCommand::Key(c) => match c {
+            UserKeyCommand::Add(params) => params.user.key_add(&params.key_file)?,
+            UserKeyCommand::Remove(params) => params.user.key_remove(&params.key)?,
+            UserKeyCommand::List(params) => params.user.print_keys(format)?,
+        },
     }
     Ok(())
 }
 
-fn maintainer_cmd(command: MaintainerCommand) -> DResult<()> {
+fn maintainer_cmd(command: MaintainerCommand, format: Format) -> DResult<()> {
     match command {
-        MaintainerCommand::Set(m) => m.user.maintainer_set(&m.repository)?,
-        MaintainerCommand::Unset(m) => m.user.maintainer_unset(&m.repository)?,
+        MaintainerCommand::Set(m) if m.branches.is_empty() => {
+            m.user.maintainer_set(&m.repository)?;
+        }
+        MaintainerCommand::Set(m) => {
+            for branch in &m.branches {
+                m.user.maintainer_set_branch(&m.repository, branch)?;
+            }
+        }
+        MaintainerCommand::Unset(m) if m.branches.is_empty() => {
+            m.user.maintainer_unset(&m.repository)?;
+        }
+        MaintainerCommand::Unset(m) => {
+            for branch in &m.branches {
+                m.user.maintainer_unset_branch(&m.repository, branch)?;
+            }
+        }
+        MaintainerCommand::List(params) => params.repository.print_maintainers(format)?,
     }
     Ok(())
 }
 
-fn main() -> DResult<()> {
+fn team_cmd(command: TeamCommand, format: Format) -> DResult<()> {
+    match command {
+        TeamCommand::Create(params) => params.team.create()?,
+        TeamCommand::Destroy(params) => params.team.destroy()?,
+        TeamCommand::Add(params) => params.team.add(&params.user)?,
+        TeamCommand::Remove(params) => params.team.remove(&params.user)?,
+        TeamCommand::List(params) => Team::print_all(params.short, format)?,
+        TeamCommand::Grant(params) => params.team.grant(&params.repository)?,
+        TeamCommand::Revoke(params) => params.team.revoke(&params.repository)?,
+    }
+    Ok(())
+}
+
+fn namespace_cmd(command: NamespaceCommand, format: Format) -> DResult<()> {
+    match command {
+        NamespaceCommand::Grant(params) => {
+            params.namespace.grant(&params.user, params.maintainer)?;
+        }
+        NamespaceCommand::Revoke(params) => params.namespace.revoke(&params.user)?,
+        NamespaceCommand::List(params) => Namespace::print_all(params.short, format)?,
+    }
+    Ok(())
+}
+
+fn hook_cmd(command: HookCommand) -> DResult<()> {
+    match command {
+        HookCommand::Update(params) => {
+            hook::update(&params.refname, &params.oldrev, &params.newrev)?;
+        }
+        HookCommand::PreReceive => hook::pre_receive()?,
+        HookCommand::PostReceive => hook::post_receive()?,
+        HookCommand::Deliver => hook::deliver()?,
+    }
+    Ok(())
+}
+
+fn config_cmd(command: &ConfigCommand) {
+    match command {
+        ConfigCommand::Show => config().print(),
+    }
+}
+
+fn main() -> ExitCode {
+    let config_path = config::path_from_args(std::env::args_os());
+    match Config::load(config_path.as_deref()) {
+        Ok(v) => config::set_config(v),
+        Err(e) => {
+            let e = ErrorKind::from_boxed(e);
+            e.report(Format::Human);
+            return ExitCode::from(e.exit_code());
+        }
+    }
     let args = Args::parse();
+    let format = args.output;
+    if let Err(e) = run(args) {
+        let e = ErrorKind::from_boxed(e);
+        e.report(format);
+        return ExitCode::from(e.exit_code());
+    }
+    ExitCode::SUCCESS
+}
+
+fn run(args: Args) -> DResult<()> {
     VERBOSE.store(args.verbose, atomic::Ordering::SeqCst);
+    if args.dry_run {
+        DRY_RUN.store(true, atomic::Ordering::SeqCst);
+        system::set_system(Arc::new(system::DryRun::new(system::system())));
+    }
     match args.command {
-        Command::Repo(c) => repo_cmd(c)?,
-        Command::User(c) => user_cmd(c)?,
-        Command::Maintainer(c) => maintainer_cmd(c)?,
+        Command::Repo(c) => repo_cmd(c, args.output)?,
+        Command::User(c) => user_cmd(c, args.output)?,
+        Command::Maintainer(c) => maintainer_cmd(c, args.output)?,
+        Command::Team(c) => team_cmd(c, args.output)?,
+        Command::Namespace(c) => namespace_cmd(c, args.output)?,
+        Command::Config(c) => config_cmd(&c),
+        Command::Plan(p) => State::load(&p.file)?.print_plan()?,
+        Command::Apply(p) => State::load(&p.file)?.apply()?,
+        Command::Doctor(p) => doctor::run(p.fix, args.output)?,
+        Command::Deliver(p) => delivery::run(p.repositories, p.retry, p.force)?,
+        Command::Backup(p) => backup::backup(&p.dir, p.repositories)?,
+        Command::Restore(p) => backup::restore(&p.bundle, p.name.as_ref())?,
+        Command::Hook(c) => hook_cmd(c)?,
+        Command::ExportState(p) => print!("{}", State::export(p.with_secrets)?.to_toml()?),
+    }
+    if args.dry_run {
+        eprintln!("{}", "Dry run, no changes have been made".magenta());
     }
     Ok(())
 }
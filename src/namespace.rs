@@ -0,0 +1,415 @@
+use crate::config::config;
+use crate::ops::{self, chmod};
+use crate::output::{self, tsv_list, Format, Tabular};
+use crate::repo::Repository;
+use crate::repo_config::RepoConfig;
+use crate::user::User;
+use crate::{cmd, failed, DResult, ErrorKind};
+use bmart_derive::Sorting;
+use colored::{ColoredString, Colorize};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// A repository name prefix, e.g. "backend" covers backend/api and backend/web/app. Grants are
+/// stored in the namespace config as multi-valued namespace.<name>.user and
+/// namespace.<name>.maintainer keys and are applied to all existing and future repositories
+#[derive(Clone, Sorting)]
+#[sorting(id = "name")]
+pub struct Namespace {
+    name: String,
+}
+
+#[derive(Serialize)]
+pub struct NamespaceInfo {
+    pub name: String,
+    pub users: Vec<String>,
+    pub maintainers: Vec<String>,
+    /// including the grants of parent namespaces
+    pub effective_users: Vec<String>,
+    pub effective_maintainers: Vec<String>,
+    pub repos: Vec<String>,
+}
+
+impl Tabular for NamespaceInfo {
+    fn header() -> &'static [&'static str] {
+        &[
+            "name",
+            "users",
+            "maintainers",
+            "effective_users",
+            "effective_maintainers",
+            "repos",
+        ]
+    }
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            tsv_list(&self.users),
+            tsv_list(&self.maintainers),
+            tsv_list(&self.effective_users),
+            tsv_list(&self.effective_maintainers),
+            tsv_list(&self.repos),
+        ]
+    }
+}
+
+impl FromStr for Namespace {
+    type Err = ErrorKind;
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let name = name.trim_end_matches('/');
+        if name.is_empty()
+            || name.split('/').any(|c| {
+                c.is_empty()
+                    || c.starts_with('.')
+                    || c.starts_with('-')
+                    || !c
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+            })
+        {
+            return Err(ErrorKind::InvalidName(format!(
+                "invalid namespace: {}",
+                name
+            )));
+        }
+        #[allow(clippy::case_sensitive_file_extension_comparisons)]
+        if name.ends_with(".git") || name.contains(".git/") {
+            return Err(ErrorKind::InvalidName(
+                "namespace can not end with or contain .git in path chunks".to_owned(),
+            ));
+        }
+        Ok(Self {
+            name: name.to_owned(),
+        })
+    }
+}
+
+fn store_path() -> PathBuf {
+    config().git_path.join(".config/gmg/namespaces")
+}
+
+fn load_store() -> DResult<RepoConfig> {
+    let path = store_path();
+    if path.exists() {
+        RepoConfig::load(&path)
+    } else {
+        Ok(RepoConfig::default())
+    }
+}
+
+/// Returns the parent namespace names of the repository or namespace, e.g. "a/b/c" gives "a"
+/// and "a/b"
+fn parents(name: &str) -> impl Iterator<Item = &str> {
+    name.match_indices('/').map(move |(pos, _)| &name[..pos])
+}
+
+impl Namespace {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn name_colored(&self) -> ColoredString {
+        format!("{}/", self.name).blue().bold()
+    }
+    pub fn contains(&self, repo: &Repository) -> bool {
+        repo.name()
+            .strip_prefix(self.name.as_str())
+            .is_some_and(|rest| rest.starts_with('/'))
+    }
+    fn key(&self, role: &str) -> String {
+        format!("namespace.{}.{}", self.name, role)
+    }
+    /// Namespaces with grants
+    pub fn list() -> DResult<Vec<Namespace>> {
+        let store = load_store()?;
+        let mut names = store.subsection_names("namespace", "user");
+        names.extend(store.subsection_names("namespace", "maintainer"));
+        names.sort();
+        names.dedup();
+        names.iter().map(|n| Ok(n.parse()?)).collect()
+    }
+    /// Namespaces with grants, which contain the repository
+    pub fn for_repo(repo: &Repository) -> DResult<Vec<Namespace>> {
+        Ok(Self::list()?
+            .into_iter()
+            .filter(|ns| ns.contains(repo))
+            .collect())
+    }
+    /// Logins granted access
+    pub fn users(&self) -> DResult<Vec<String>> {
+        let mut result = load_store()?.get_all(&self.key("user"));
+        result.sort();
+        Ok(result)
+    }
+    pub fn maintainers(&self) -> DResult<Vec<String>> {
+        let mut result = load_store()?.get_all(&self.key("maintainer"));
+        result.sort();
+        Ok(result)
+    }
+    pub fn repos(&self) -> DResult<Vec<Repository>> {
+        Ok(Repository::list()?
+            .into_iter()
+            .filter(|r| self.contains(r))
+            .collect())
+    }
+    fn store_add(&self, role: &str, login: &str) -> DResult<()> {
+        let path = store_path();
+        if let Some(dir) = path.parent() {
+            ops::create_dir_all(dir)?;
+        }
+        cmd!("git", "config", "-f", &path, "--add", self.key(role), login)
+            .mutating()
+            .run()?;
+        chmod(&path, 0o644)?;
+        Ok(())
+    }
+    fn store_remove(&self, role: &str, login: &str) -> DResult<()> {
+        let path = store_path();
+        cmd!(
+            "git",
+            "config",
+            "-f",
+            &path,
+            "--fixed-value",
+            "--unset-all",
+            self.key(role),
+            login
+        )
+        .mutating()
+        .run()?;
+        Ok(())
+    }
+    /// Grants access to all repositories of the namespace, including ones created later.
+    /// Maintainers get access as well. Archived repositories get the grants when unarchived
+    pub fn grant(&self, user: &User, maintainer: bool) -> DResult<()> {
+        user.exists()?;
+        let mut repos = Vec::new();
+        for repo in self.repos()? {
+            if !repo.read_config()?.is_archived() {
+                repos.push(repo);
+            }
+        }
+        // users with access or the maintainer role before the grant keep them when the namespace
+        // is revoked
+        for repo in &repos {
+            if repo
+                .direct_users()?
+                .iter()
+                .any(|u| u.login() == user.login())
+            {
+                repo.set_flag(&format!("gmg.user.{}.direct", user.login()), true)?;
+            }
+            if maintainer && repo.direct_maintainers()?.iter().any(|m| m == user.login()) {
+                repo.set_flag(&format!("gmg.user.{}.directmaintainer", user.login()), true)?;
+            }
+        }
+        for repo in &repos {
+            user.attach(repo)?;
+            if maintainer {
+                repo.set_flag(&format!("hooks.user.{}.maintainer", user.login()), true)?;
+            }
+        }
+        if !self.users()?.iter().any(|u| u == user.login()) {
+            self.store_add("user", user.login())?;
+        }
+        if maintainer && !self.maintainers()?.iter().any(|u| u == user.login()) {
+            self.store_add("maintainer", user.login())?;
+        }
+        user.update_cgit()?;
+        println!(
+            "User {} has been {} {}access to namespace {}",
+            user.login_colored(),
+            "granted".green().bold(),
+            if maintainer { "maintainer " } else { "" },
+            self.name_colored()
+        );
+        Ok(())
+    }
+    /// Revokes all the namespace roles of the user. Repository access and the maintainer role
+    /// granted directly, by teams or by other namespaces are kept
+    pub fn revoke(&self, user: &User) -> DResult<()> {
+        let was_maintainer = self.maintainers()?.iter().any(|u| u == user.login());
+        if was_maintainer {
+            self.store_remove("maintainer", user.login())?;
+        }
+        if self.users()?.iter().any(|u| u == user.login()) {
+            self.store_remove("user", user.login())?;
+        } else if !was_maintainer {
+            return failed!(
+                NotFound,
+                format!(
+                    "User {} has no access to namespace {}",
+                    user.login(),
+                    self.name
+                )
+            );
+        }
+        let mut detached = false;
+        for repo in self.repos()? {
+            let namespaces = Self::for_repo(&repo)?;
+            if was_maintainer {
+                let mut maintainer = repo
+                    .read_config()?
+                    .direct_maintainers()
+                    .iter()
+                    .any(|m| m == user.login());
+                for ns in &namespaces {
+                    if ns.maintainers()?.iter().any(|u| u == user.login()) {
+                        maintainer = true;
+                    }
+                }
+                if !maintainer {
+                    repo.set_flag(&format!("hooks.user.{}.maintainer", user.login()), false)?;
+                }
+            }
+            detached |= user.detach_if_unused(&repo)?;
+        }
+        if detached {
+            user.update_cgit()?;
+        }
+        println!(
+            "User {} has been {} access to namespace {}",
+            user.login_colored(),
+            "revoked".red().bold(),
+            self.name_colored()
+        );
+        Ok(())
+    }
+    /// Removes all grants of the user, called when the user is destroyed
+    pub fn remove_user(login: &str) -> DResult<()> {
+        for ns in Self::list()? {
+            for role in ["user", "maintainer"] {
+                if load_store()?
+                    .get_all(&ns.key(role))
+                    .iter()
+                    .any(|u| u == login)
+                {
+                    ns.store_remove(role, login)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Applies grants of the namespaces the repository belongs to, called on creation. Archived
+    /// repositories are skipped
+    pub fn apply_inherited(repo: &Repository) -> DResult<()> {
+        if repo.read_config()?.is_archived() {
+            return Ok(());
+        }
+        let mut maintainers = BTreeSet::new();
+        let mut users = BTreeSet::new();
+        for ns in Self::for_repo(repo)? {
+            users.extend(ns.users()?);
+            maintainers.extend(ns.maintainers()?);
+        }
+        for login in users.union(&maintainers) {
+            let user: User = login.parse()?;
+            user.attach(repo)?;
+            let maintainer = maintainers.contains(login);
+            if maintainer {
+                repo.set_flag(&format!("hooks.user.{}.maintainer", login), true)?;
+            }
+            user.update_cgit()?;
+            println!(
+                "User {} has been {} {}access to {} by namespace",
+                user.login_colored(),
+                "granted".green().bold(),
+                if maintainer { "maintainer " } else { "" },
+                repo.name_colored()
+            );
+        }
+        Ok(())
+    }
+    fn info(&self, repos: &[Repository]) -> DResult<NamespaceInfo> {
+        let mut effective_users = BTreeSet::new();
+        let mut effective_maintainers = BTreeSet::new();
+        for name in parents(&self.name).chain(std::iter::once(self.name.as_str())) {
+            let ns: Namespace = name.parse()?;
+            effective_users.extend(ns.users()?);
+            effective_maintainers.extend(ns.maintainers()?);
+        }
+        Ok(NamespaceInfo {
+            name: self.name.clone(),
+            users: self.users()?,
+            maintainers: self.maintainers()?,
+            effective_users: effective_users.into_iter().collect(),
+            effective_maintainers: effective_maintainers.into_iter().collect(),
+            repos: repos
+                .iter()
+                .filter(|r| r.name().rsplit_once('/').map(|(p, _)| p) == Some(&self.name))
+                .map(|r| r.name().to_owned())
+                .collect(),
+        })
+    }
+    /// Prints the namespace tree: namespaces with grants and ones which contain repositories
+    pub fn print_all(short: bool, format: Format) -> DResult<()> {
+        let repos = Repository::list()?;
+        let mut names: BTreeSet<String> = BTreeSet::new();
+        for ns in Self::list()? {
+            names.extend(parents(ns.name()).map(ToOwned::to_owned));
+            names.insert(ns.name);
+        }
+        for repo in &repos {
+            names.extend(parents(repo.name()).map(ToOwned::to_owned));
+        }
+        let mut namespaces: Vec<Namespace> =
+            names.iter().map(|n| n.parse()).collect::<Result<_, _>>()?;
+        // tree order: a parent is followed by its children
+        namespaces.sort_by(|a, b| a.name.split('/').cmp(b.name.split('/')));
+        let mut infos = Vec::new();
+        for ns in &namespaces {
+            infos.push(ns.info(&repos)?);
+        }
+        if !format.is_human() {
+            return output::print_list(format, &infos);
+        }
+        for info in infos {
+            if short {
+                println!("{}", info.name);
+                continue;
+            }
+            let depth = info.name.matches('/').count();
+            let indent = "  ".repeat(depth);
+            let mut line = format!(
+                "{}{}",
+                indent,
+                format!("{}/", info.name.rsplit('/').next().unwrap_or_default())
+                    .blue()
+                    .bold()
+            );
+            for (title, own, effective) in [
+                ("users", &info.users, &info.effective_users),
+                (
+                    "maintainers",
+                    &info.maintainers,
+                    &info.effective_maintainers,
+                ),
+            ] {
+                if effective.is_empty() {
+                    continue;
+                }
+                let logins: Vec<String> = effective
+                    .iter()
+                    .map(|login| {
+                        if own.contains(login) {
+                            login.yellow().to_string()
+                        } else {
+                            format!("{} (inherited)", login.yellow())
+                        }
+                    })
+                    .collect();
+                line.push_str(&format!(" {}: {}", title, logins.join(", ")));
+            }
+            println!("{}", line);
+            for repo in info.repos {
+                println!(
+                    "{}  {}",
+                    indent,
+                    repo.rsplit('/').next().unwrap_or_default().cyan().bold()
+                );
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+// File system operations which modify the server. In dry-run mode the operations are only
+// reported, created paths are remembered so the following steps can refer to them
+use crate::{cmd, dry_run, DResult};
+use colored::Colorize;
+use lazy_static::lazy_static;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref PLANNED: Mutex<BTreeSet<PathBuf>> = <_>::default();
+}
+
+/// Prints a step skipped in dry-run mode to stderr
+pub fn report<D: fmt::Display>(action: D) {
+    eprintln!("{} {}", "[dry-run]".magenta().bold(), action);
+}
+
+fn plan(path: &Path) {
+    PLANNED.lock().unwrap().insert(path.to_owned());
+}
+
+/// Checks if the path exists or would be created in dry-run mode
+///
+/// # Panics
+///
+/// Will panic if the mutex is poisoned
+pub fn exists<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    path.exists() || PLANNED.lock().unwrap().contains(path)
+}
+
+pub fn chmod<P: AsRef<Path>>(path: P, permissions: u32) -> DResult<()> {
+    if dry_run() {
+        report(format!(
+            "chmod {:o} {}",
+            permissions,
+            path.as_ref().to_string_lossy()
+        ));
+        return Ok(());
+    }
+    fs::set_permissions(path, fs::Permissions::from_mode(permissions))?;
+    Ok(())
+}
+
+/// Recursively sets permissions for directories and files, symlinks are skipped
+pub fn chmod_tree<P: AsRef<Path>>(
+    path: P,
+    dir_permissions: u32,
+    file_permissions: u32,
+) -> DResult<()> {
+    let path = path.as_ref();
+    if dry_run() {
+        report(format!(
+            "chmod -R {:o}/{:o} {}",
+            dir_permissions,
+            file_permissions,
+            path.to_string_lossy()
+        ));
+        return Ok(());
+    }
+    let meta = fs::symlink_metadata(path)?;
+    if meta.is_dir() {
+        chmod(path, dir_permissions)?;
+        for entry in fs::read_dir(path)? {
+            chmod_tree(entry?.path(), dir_permissions, file_permissions)?;
+        }
+    } else if meta.is_file() {
+        chmod(path, file_permissions)?;
+    }
+    Ok(())
+}
+
+pub fn create_dir_all<P: AsRef<Path>>(path: P) -> DResult<()> {
+    let path = path.as_ref();
+    if dry_run() {
+        if !exists(path) {
+            report(format!("mkdir -p {}", path.to_string_lossy()));
+            plan(path);
+        }
+        return Ok(());
+    }
+    fs::create_dir_all(path)?;
+    Ok(())
+}
+
+pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> DResult<()> {
+    let path = path.as_ref();
+    if dry_run() {
+        report(format!(
+            "write {} ({} bytes)",
+            path.to_string_lossy(),
+            contents.as_ref().len()
+        ));
+        plan(path);
+        return Ok(());
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+pub fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> DResult<()> {
+    let (original, link) = (original.as_ref(), link.as_ref());
+    if dry_run() {
+        report(format!(
+            "ln -s {} {}",
+            original.to_string_lossy(),
+            link.to_string_lossy()
+        ));
+        return Ok(());
+    }
+    std::os::unix::fs::symlink(original, link)?;
+    Ok(())
+}
+
+pub fn remove_file<P: AsRef<Path>>(path: P) -> DResult<()> {
+    let path = path.as_ref();
+    if dry_run() {
+        if fs::symlink_metadata(path).is_ok() {
+            report(format!("rm {}", path.to_string_lossy()));
+        }
+        return Ok(());
+    }
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+pub fn remove_dir_all<P: AsRef<Path>>(path: P) -> DResult<()> {
+    let path = path.as_ref();
+    if dry_run() {
+        report(format!("rm -rf {}", path.to_string_lossy()));
+        return Ok(());
+    }
+    fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+/// Moves a file or a directory, the target is planned in dry-run mode
+pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(source: P, target: Q) -> DResult<()> {
+    let (source, target) = (source.as_ref(), target.as_ref());
+    if dry_run() {
+        report(format!(
+            "mv {} {}",
+            source.to_string_lossy(),
+            target.to_string_lossy()
+        ));
+        plan(target);
+        return Ok(());
+    }
+    fs::rename(source, target)?;
+    Ok(())
+}
+
+/// Removes everything inside the directory
+pub fn clear_dir<P: AsRef<Path>>(path: P) -> DResult<()> {
+    let path = path.as_ref();
+    if dry_run() {
+        report(format!("rm -rf {}/*", path.to_string_lossy()));
+        return Ok(());
+    }
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if fs::symlink_metadata(&path)?.is_dir() {
+            fs::remove_dir_all(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies the directory contents, preserving permissions
+pub fn copy_dir_contents<P: AsRef<Path>, Q: AsRef<Path>>(source: P, target: Q) -> DResult<()> {
+    let mut source = source.as_ref().to_owned();
+    source.push(".");
+    cmd!("cp", "-pRf", source, target.as_ref())
+        .mutating()
+        .run()?;
+    Ok(())
+}
+
+/// Recursively removes empty directories, repository (*.git) directories are never entered
+pub fn remove_empty_dirs<P: AsRef<Path>>(path: P) {
+    let path = path.as_ref();
+    if !fs::symlink_metadata(path).is_ok_and(|m| m.is_dir()) {
+        return;
+    }
+    if path.extension().is_some_and(|ext| ext == "git") {
+        return;
+    }
+    if dry_run() {
+        report(format!("remove empty dirs in {}", path.to_string_lossy()));
+        return;
+    }
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            remove_empty_dirs(entry.path());
+        }
+    }
+    let _r = fs::remove_dir(path);
+}
@@ -0,0 +1,80 @@
+use crate::DResult;
+use bmart_derive::EnumStr;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Eq, PartialEq, EnumStr)]
+#[enumstr(rename_all = "lowercase")]
+pub enum Format {
+    Human,
+    Json,
+    Yaml,
+    Tsv,
+}
+
+impl Format {
+    #[inline]
+    pub fn is_human(self) -> bool {
+        self == Format::Human
+    }
+}
+
+/// Data which can be printed as a TSV table
+pub trait Tabular {
+    fn header() -> &'static [&'static str];
+    fn row(&self) -> Vec<String>;
+}
+
+/// Formats a list value for a TSV cell
+pub fn tsv_list<S: AsRef<str>>(values: &[S]) -> String {
+    values
+        .iter()
+        .map(AsRef::as_ref)
+        .collect::<Vec<&str>>()
+        .join(",")
+}
+
+fn tsv_cell(value: &str) -> String {
+    value.replace(['\t', '\n', '\r'], " ")
+}
+
+fn print_tsv_row(row: &[String]) {
+    println!(
+        "{}",
+        row.iter()
+            .map(|v| tsv_cell(v))
+            .collect::<Vec<String>>()
+            .join("\t")
+    );
+}
+
+fn print_tsv_header<T: Tabular>() {
+    println!("{}", T::header().join("\t"));
+}
+
+/// Prints a list in a machine-readable format, human output is up to the caller
+pub fn print_list<T: Serialize + Tabular>(format: Format, items: &[T]) -> DResult<()> {
+    match format {
+        Format::Human | Format::Json => println!("{}", serde_json::to_string_pretty(items)?),
+        Format::Yaml => print!("{}", serde_yaml::to_string(items)?),
+        Format::Tsv => {
+            print_tsv_header::<T>();
+            for item in items {
+                print_tsv_row(&item.row());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints a single item in a machine-readable format, human output is up to the caller
+pub fn print_item<T: Serialize + Tabular>(format: Format, item: &T) -> DResult<()> {
+    match format {
+        Format::Human | Format::Json => println!("{}", serde_json::to_string_pretty(item)?),
+        Format::Yaml => print!("{}", serde_yaml::to_string(item)?),
+        Format::Tsv => {
+            print_tsv_header::<T>();
+            print_tsv_row(&item.row());
+        }
+    }
+    Ok(())
+}
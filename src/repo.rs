@@ -1,22 +1,270 @@
+use crate::audit;
+use crate::config::{valid_branch_rule, MAX_GROUP_LEN};
+use crate::delivery::{self, Delivery, Kind, Status};
+use crate::hook;
+use crate::mail::valid_email;
+use crate::namespace::Namespace;
+use crate::ops::{self, chmod, chmod_tree};
+use crate::output::{self, tsv_list, Format, Tabular};
+use crate::repo_config::{BranchMaintainer, Notification, Rci, RepoConfig, Webhook};
+use crate::system::system;
+use crate::team::Team;
+use crate::time;
+use crate::trash::{self, TrashEntry};
 use crate::user::User;
-use crate::{
-    chmod, err, failed, sh, sh_any, DResult, ErrorKind, GIT_PATH, GIT_USER, GROUP_PFX, MAIN_BRANCH,
-    PROTECTED_BRANCHES, VERSION,
-};
-use bmart_derive::Sorting;
+use crate::webhook::Payload;
+use crate::{cmd, config, dry_run, err, failed, DResult, ErrorKind, VERSION};
+use bmart_derive::{EnumStr, Sorting};
 use colored::{ColoredString, Colorize};
-use configparser::ini::Ini;
-use std::env::set_current_dir;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use tempdir::TempDir;
 
+#[derive(Serialize)]
+pub struct RepoInfo {
+    pub name: String,
+    pub group: String,
+    pub path: String,
+    pub description: Option<String>,
+    pub branches: Vec<String>,
+    pub protected_branches: Vec<String>,
+    pub protected_tags: Vec<String>,
+    pub users: Vec<String>,
+    pub read_only_users: Vec<String>,
+    pub maintainers: Vec<String>,
+    pub branch_maintainers: Vec<BranchMaintainer>,
+    pub rci: Vec<Rci>,
+    pub archived: bool,
+    pub archived_at: Option<u64>,
+    /// members at the time the repository has been archived
+    pub archived_members: Vec<String>,
+}
+
+impl Tabular for RepoInfo {
+    fn header() -> &'static [&'static str] {
+        &[
+            "name",
+            "group",
+            "path",
+            "description",
+            "branches",
+            "protected_branches",
+            "protected_tags",
+            "users",
+            "read_only_users",
+            "maintainers",
+            "branch_maintainers",
+            "rci",
+            "archived",
+            "archived_at",
+            "archived_members",
+        ]
+    }
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.group.clone(),
+            self.path.clone(),
+            self.description.clone().unwrap_or_default(),
+            tsv_list(&self.branches),
+            tsv_list(&self.protected_branches),
+            tsv_list(&self.protected_tags),
+            tsv_list(&self.users),
+            tsv_list(&self.read_only_users),
+            tsv_list(&self.maintainers),
+            tsv_list(
+                &self
+                    .branch_maintainers
+                    .iter()
+                    .map(|m| format!("{}={}", m.login, m.branch))
+                    .collect::<Vec<String>>(),
+            ),
+            tsv_list(
+                &self
+                    .rci
+                    .iter()
+                    .map(|r| format!("{}={}", r.branch, r.url))
+                    .collect::<Vec<String>>(),
+            ),
+            self.archived.to_string(),
+            self.archived_at.map(|t| t.to_string()).unwrap_or_default(),
+            tsv_list(&self.archived_members),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct WebhookInfo {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub signed: bool,
+    pub last_delivery: Option<Delivery>,
+}
+
+impl Tabular for WebhookInfo {
+    fn header() -> &'static [&'static str] {
+        &[
+            "name",
+            "url",
+            "refs",
+            "timeout",
+            "signed",
+            "last_delivery",
+            "last_delivery_status",
+        ]
+    }
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.webhook.name.clone(),
+            self.webhook.url.clone(),
+            tsv_list(&self.webhook.refs),
+            self.webhook.timeout.to_string(),
+            self.signed.to_string(),
+            self.last_delivery
+                .as_ref()
+                .and_then(|d| d.last_attempt)
+                .map(|t| t.to_string())
+                .unwrap_or_default(),
+            self.last_delivery
+                .as_ref()
+                .map(|d| d.status.to_string())
+                .unwrap_or_default(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct NotificationInfo {
+    #[serde(flatten)]
+    pub notification: Notification,
+    pub last_delivery: Option<Delivery>,
+}
+
+impl Tabular for NotificationInfo {
+    fn header() -> &'static [&'static str] {
+        &["email", "branches", "last_delivery", "last_delivery_status"]
+    }
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.notification.email.clone(),
+            tsv_list(&self.notification.branches),
+            self.last_delivery
+                .as_ref()
+                .and_then(|d| d.last_attempt)
+                .map(|t| t.to_string())
+                .unwrap_or_default(),
+            self.last_delivery
+                .as_ref()
+                .map(|d| d.status.to_string())
+                .unwrap_or_default(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct MaintainerInfo {
+    pub login: String,
+    /// repository-wide maintainer
+    pub all_branches: bool,
+    /// branch names and patterns
+    pub branches: Vec<String>,
+}
+
+impl Tabular for MaintainerInfo {
+    fn header() -> &'static [&'static str] {
+        &["login", "all_branches", "branches"]
+    }
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.login.clone(),
+            self.all_branches.to_string(),
+            tsv_list(&self.branches),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct BranchInfo {
+    pub name: String,
+    pub protected: bool,
+    /// the branch name or the glob pattern which protects the branch
+    pub rule: Option<String>,
+}
+
+impl Tabular for BranchInfo {
+    fn header() -> &'static [&'static str] {
+        &["name", "protected", "rule"]
+    }
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.protected.to_string(),
+            self.rule.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct RepoUserInfo {
+    pub login: String,
+    pub access: Access,
+    pub maintainer: bool,
+    /// granted directly, not only through teams or namespaces
+    pub direct: bool,
+    pub teams: Vec<String>,
+    pub namespaces: Vec<String>,
+}
+
+impl Tabular for RepoUserInfo {
+    fn header() -> &'static [&'static str] {
+        &[
+            "login",
+            "access",
+            "maintainer",
+            "direct",
+            "teams",
+            "namespaces",
+        ]
+    }
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.login.clone(),
+            self.access.to_string(),
+            self.maintainer.to_string(),
+            self.direct.to_string(),
+            tsv_list(&self.teams),
+            tsv_list(&self.namespaces),
+        ]
+    }
+}
+
+/// User access level to a repository
+#[derive(Serialize, Clone, Copy, Eq, PartialEq, EnumStr)]
+#[serde(rename_all = "lowercase")]
+#[enumstr(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    Write,
+}
+
+impl Access {
+    pub fn from_read_only(read_only: bool) -> Self {
+        if read_only {
+            Access::Read
+        } else {
+            Access::Write
+        }
+    }
+}
+
 #[derive(Clone, Sorting)]
 #[sorting(id = "name")]
 pub struct Repository {
     name: String,
-    group: String,
     path: PathBuf,
 }
 
@@ -24,27 +272,26 @@ impl FromStr for Repository {
     type Err = ErrorKind;
     fn from_str(name: &str) -> Result<Self, Self::Err> {
         if name.starts_with('/') {
-            return Err(ErrorKind::Failed(
+            return Err(ErrorKind::InvalidName(
                 "repository name can not start with /".to_owned(),
             ));
         }
+        // dot dirs (e.g. the trash) are not scanned for repositories
+        if name.split('/').any(|chunk| chunk.starts_with('.')) {
+            return Err(ErrorKind::InvalidName(
+                "repository path chunks can not start with .".to_owned(),
+            ));
+        }
         #[allow(clippy::case_sensitive_file_extension_comparisons)]
         if name.ends_with(".git") || name.contains(".git/") {
-            return Err(ErrorKind::Failed(
+            return Err(ErrorKind::InvalidName(
                 "repository name can not end with or contain .git in path chunks".to_owned(),
             ));
         }
-        if name.len() > 30 {
-            return Err(ErrorKind::Failed(
-                "repository name is longer than 30 chars".to_owned(),
-            ));
-        }
-        let group = format!("{}{}", GROUP_PFX, name);
-        let mut path = GIT_PATH.clone();
+        let mut path = config().git_path.clone();
         path.push(format!("{}.git", name));
         Ok(Self {
             name: name.to_owned(),
-            group,
             path,
         })
     }
@@ -71,7 +318,7 @@ impl Repository {
         self.exists()?;
         let mut path = self.path.clone();
         path.push("description");
-        fs::write(path, desc.unwrap_or("Unnamed repository;"))?;
+        ops::write(path, desc.unwrap_or("Unnamed repository;"))?;
         for user in self.users()? {
             user.update_cgit()?;
         }
@@ -83,51 +330,451 @@ impl Repository {
     pub fn short_name(&self) -> &str {
         self.name.rsplit('/').next().unwrap()
     }
-    pub fn group(&self) -> &str {
-        &self.group
+    /// The POSIX group, mapped with gmg.group in the repository config. Repositories created
+    /// before the mapping has been introduced use the prefixed name
+    pub fn group(&self) -> DResult<String> {
+        if self.path.join("config").exists() {
+            if let Some(group) = self.read_config()?.get("gmg.group") {
+                return Ok(group.to_owned());
+            }
+        }
+        Ok(self.legacy_group())
+    }
+    fn legacy_group(&self) -> String {
+        format!("{}{}", config().group_prefix, self.name)
+    }
+    /// Picks a free group for a new repository: the prefixed name if fits, otherwise the prefix
+    /// and a hash of the name
+    fn allocate_group(&self) -> DResult<String> {
+        let config = config();
+        let system = system();
+        let group = self.legacy_group();
+        if group.len() <= MAX_GROUP_LEN && !system.group_exists(&group)? {
+            return Ok(group);
+        }
+        for n in 0u32.. {
+            let seed = if n == 0 {
+                self.name.clone()
+            } else {
+                format!("{}#{}", self.name, n)
+            };
+            let hash: String = Sha256::digest(seed.as_bytes())
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            let group = format!(
+                "{}{}",
+                config.group_prefix,
+                &hash[..config.group_hash_len()]
+            );
+            if !system.group_exists(&group)? {
+                return Ok(group);
+            }
+        }
+        unreachable!()
+    }
+    /// Records the group mapping of repositories created before it has been introduced
+    pub fn migrate_groups() -> DResult<()> {
+        let mut migrated = 0;
+        for repo in Self::list()? {
+            if repo.read_config()?.get("gmg.group").is_none() {
+                let group = repo.legacy_group();
+                repo.set("gmg.group", &group)?;
+                println!(
+                    "Repository {} has been mapped to group {}",
+                    repo.name_colored(),
+                    group.yellow()
+                );
+                migrated += 1;
+            }
+        }
+        println!("Repositories migrated: {}", migrated);
+        Ok(())
+    }
+    /// Maps groups to repositories
+    pub fn groups() -> DResult<BTreeMap<String, Repository>> {
+        let mut result = BTreeMap::new();
+        for repo in Self::list()? {
+            result.insert(repo.group()?, repo);
+        }
+        Ok(result)
     }
     pub fn path(&self) -> &Path {
         &self.path
     }
-    pub fn path_as_str(&self) -> std::borrow::Cow<str> {
+    pub fn path_as_str(&self) -> std::borrow::Cow<'_, str> {
         self.path.to_string_lossy()
     }
-    pub fn chdir(&self) -> DResult<()> {
-        set_current_dir(&self.path)?;
-        Ok(())
-    }
-    pub fn load_config(&self) -> DResult<Ini> {
+    pub fn read_config(&self) -> DResult<RepoConfig> {
         let mut path = self.path.clone();
         path.push("config");
-        let mut config = Ini::new();
-        config.load(path)?;
-        Ok(config)
+        RepoConfig::load(&path)
     }
     pub fn exists(&self) -> DResult<()> {
-        if self.path.exists() {
+        if ops::exists(&self.path) {
             Ok(())
         } else {
-            failed!(format!("Repository doesn't exist: {}", self.name))
+            failed!(NotFound, format!("Repository doesn't exist: {}", self.name))
         }
     }
-    #[inline]
+    /// Protects the branch, the name can be a glob pattern, e.g. release/*
     pub fn protect(&self, branch: &str) -> DResult<()> {
+        if !valid_branch_rule(branch) {
+            return failed!(
+                InvalidName,
+                format!("invalid branch or pattern: {}", branch)
+            );
+        }
         self.set(&format!("hooks.branch.{}.protected", branch), "true")
     }
     #[inline]
     pub fn unprotect(&self, branch: &str) -> DResult<()> {
         self.unset(&format!("hooks.branch.{}.protected", branch))
     }
+    /// Makes matching tags immutable, deletable by maintainers only
+    pub fn protect_tags(&self, pattern: &str) -> DResult<()> {
+        if !valid_branch_rule(pattern) {
+            return failed!(InvalidName, format!("invalid tag or pattern: {}", pattern));
+        }
+        self.set(&format!("hooks.tag.{}.protected", pattern), "true")
+    }
+    #[inline]
+    pub fn unprotect_tags(&self, pattern: &str) -> DResult<()> {
+        self.unset(&format!("hooks.tag.{}.protected", pattern))
+    }
+    pub fn rci_set(&self, branch: &str, trigger_url: &str, secret: &str) -> DResult<()> {
+        self.set(&format!("hooks.branch.{branch}.rci.url"), trigger_url)?;
+        self.set(&format!("hooks.branch.{branch}.rci.secret"), secret)?;
+        println!(
+            "RCI config {} for {} branch {}, trigger URL: {}",
+            "SET".green().bold(),
+            self.name_colored(),
+            branch.yellow(),
+            trigger_url
+        );
+        Ok(())
+    }
+    pub fn rci_unset(&self, branch: &str) -> DResult<()> {
+        self.unset(&format!("hooks.branch.{branch}.rci.url"))?;
+        self.unset(&format!("hooks.branch.{branch}.rci.secret"))?;
+        println!(
+            "RCI config {} for {} branch {}",
+            "UNSET".yellow().bold(),
+            self.name_colored(),
+            branch.yellow()
+        );
+        Ok(())
+    }
+    fn webhook(&self, name: &str) -> DResult<Webhook> {
+        self.exists()?;
+        let Some(w) = self
+            .read_config()?
+            .webhooks()
+            .into_iter()
+            .find(|w| w.name == name)
+        else {
+            return failed!(
+                NotFound,
+                format!("webhook {} not found in {}", name, self.name)
+            );
+        };
+        Ok(w)
+    }
+    /// Adds a webhook, refs are branch names, full ref names or glob patterns
+    pub fn webhook_add(
+        &self,
+        name: &str,
+        url: &str,
+        secret: Option<&str>,
+        refs: &[String],
+        timeout: Option<u32>,
+    ) -> DResult<()> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return failed!(InvalidName, format!("invalid webhook name: {}", name));
+        }
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return failed!(InvalidName, format!("invalid webhook URL: {}", url));
+        }
+        if let Some(r) = refs.iter().find(|r| !valid_branch_rule(r)) {
+            return failed!(InvalidName, format!("invalid ref or pattern: {}", r));
+        }
+        if timeout == Some(0) {
+            return failed!(InvalidName, "timeout must be positive".to_owned());
+        }
+        if self.webhook(name).is_ok() {
+            return failed!(
+                AlreadyExists,
+                format!("webhook {} already exists in {}", name, self.name)
+            );
+        }
+        let key = |k: &str| format!("hooks.webhook.{}.{}", name, k);
+        self.set(&key("url"), url)?;
+        if let Some(secret) = secret {
+            self.set(&key("secret"), secret)?;
+        }
+        if let Some(timeout) = timeout {
+            self.set(&key("timeout"), &timeout.to_string())?;
+        }
+        for r in refs {
+            self.add(&key("ref"), r)?;
+        }
+        println!(
+            "Webhook {} has been {} to {}, URL: {}",
+            name.yellow(),
+            "added".green().bold(),
+            self.name_colored(),
+            url
+        );
+        Ok(())
+    }
+    pub fn webhook_remove(&self, name: &str) -> DResult<()> {
+        self.webhook(name)?;
+        cmd!(
+            "git",
+            "config",
+            "-f",
+            self.path.join("config"),
+            "--remove-section",
+            format!("hooks.webhook.{}", name)
+        )
+        .mutating()
+        .run()?;
+        println!(
+            "Webhook {} has been {} from {}",
+            name.yellow(),
+            "removed".red().bold(),
+            self.name_colored()
+        );
+        Ok(())
+    }
+    pub fn webhook_infos(&self) -> DResult<Vec<WebhookInfo>> {
+        self.exists()?;
+        let deliveries = delivery::list(self.path())?;
+        Ok(self
+            .read_config()?
+            .webhooks()
+            .into_iter()
+            .map(|w| WebhookInfo {
+                signed: w.secret.is_some(),
+                last_delivery: deliveries
+                    .iter()
+                    .rev()
+                    .find(|d| d.kind == Kind::Webhook && d.target == w.name)
+                    .cloned(),
+                webhook: w,
+            })
+            .collect())
+    }
+    pub fn print_webhooks(&self, format: Format) -> DResult<()> {
+        let webhooks = self.webhook_infos()?;
+        if !format.is_human() {
+            return output::print_list(format, &webhooks);
+        }
+        for info in webhooks {
+            let w = info.webhook;
+            let mut line = format!("{} {}", w.name.yellow(), w.url);
+            if !w.refs.is_empty() {
+                line.push_str(&format!(" [{}]", w.refs.join(", ")));
+            }
+            if info.signed {
+                line.push_str(" (signed)");
+            }
+            match info.last_delivery {
+                Some(d) if d.status == Status::Delivered => line.push_str(&format!(
+                    ", last delivery: {} {}",
+                    "ok".green(),
+                    time::ago(d.last_attempt.unwrap_or(d.created))
+                )),
+                Some(d) => line.push_str(&format!(
+                    ", last delivery: {} ({}) {}",
+                    d.status.to_string().red(),
+                    d.error.unwrap_or_default(),
+                    time::ago(d.last_attempt.unwrap_or(d.created))
+                )),
+                None => {}
+            }
+            println!("{}", line);
+        }
+        Ok(())
+    }
+    /// Sends a ping event to the webhook, the delivery is logged
+    pub fn webhook_test(&self, name: &str) -> DResult<()> {
+        let w = self.webhook(name)?;
+        if dry_run() {
+            ops::report(format!("deliver a ping event to {}", w.url));
+            return Ok(());
+        }
+        let pusher = users::get_current_username()
+            .map(|u| u.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut delivery = Delivery::new(
+            Kind::Webhook,
+            &w.name,
+            "ping",
+            None,
+            Some(serde_json::to_value(Payload::ping(&self.name, &pusher))?),
+        );
+        delivery.attempt(&self.read_config()?)?;
+        if delivery.status != Status::Delivered {
+            // test events are not retried
+            delivery.status = Status::Failed;
+            delivery.next_attempt = None;
+        }
+        delivery.save(self.path())?;
+        if delivery.status != Status::Delivered {
+            return failed!(format!(
+                "webhook {} delivery failed: {}",
+                name,
+                delivery.error.unwrap_or_default()
+            ));
+        }
+        println!(
+            "Webhook {} {} (HTTP {})",
+            name.yellow(),
+            "delivered".green().bold(),
+            delivery.code.unwrap_or_default()
+        );
+        Ok(())
+    }
+    /// Adds an email push notification recipient for the branches (names or glob patterns, all
+    /// branches if empty)
+    pub fn notify_add(&self, email: &str, branches: &[String]) -> DResult<()> {
+        if !valid_email(email) {
+            return failed!(InvalidName, format!("invalid email address: {}", email));
+        }
+        if let Some(b) = branches.iter().find(|b| !valid_branch_rule(b)) {
+            return failed!(InvalidName, format!("invalid branch or pattern: {}", b));
+        }
+        self.exists()?;
+        let current = self
+            .read_config()?
+            .notifications()
+            .into_iter()
+            .find(|n| n.email == email)
+            .map(|n| n.branches)
+            .unwrap_or_default();
+        let all = ["*".to_owned()];
+        let branches = if branches.is_empty() { &all } else { branches };
+        let key = format!("hooks.notify.{}.branch", email);
+        for b in branches {
+            if !current.contains(b) {
+                self.add(&key, b)?;
+            }
+        }
+        println!(
+            "Notifications for {} have been {} to {}, branches: {}",
+            email.yellow(),
+            "added".green().bold(),
+            self.name_colored(),
+            branches.join(", ")
+        );
+        Ok(())
+    }
+    /// Removes the recipient branches, the recipient is removed completely if no branches are
+    /// specified
+    pub fn notify_remove(&self, email: &str, branches: &[String]) -> DResult<()> {
+        self.exists()?;
+        let Some(n) = self
+            .read_config()?
+            .notifications()
+            .into_iter()
+            .find(|n| n.email == email)
+        else {
+            return failed!(
+                NotFound,
+                format!("{} is not notified on pushes to {}", email, self.name)
+            );
+        };
+        let key = format!("hooks.notify.{}.branch", email);
+        if branches.is_empty() {
+            cmd!(
+                "git",
+                "config",
+                "-f",
+                self.path.join("config"),
+                "--remove-section",
+                format!("hooks.notify.{}", email)
+            )
+            .mutating()
+            .run()?;
+        } else {
+            for b in branches {
+                if !n.branches.contains(b) {
+                    return failed!(
+                        NotFound,
+                        format!(
+                            "{} is not notified on pushes to {} branch {}",
+                            email, self.name, b
+                        )
+                    );
+                }
+            }
+            for b in branches {
+                self.unset_value(&key, b)?;
+            }
+        }
+        println!(
+            "Notifications for {} have been {} from {}",
+            email.yellow(),
+            "removed".red().bold(),
+            self.name_colored()
+        );
+        Ok(())
+    }
+    pub fn notification_infos(&self) -> DResult<Vec<NotificationInfo>> {
+        self.exists()?;
+        let deliveries = delivery::list(self.path())?;
+        Ok(self
+            .read_config()?
+            .notifications()
+            .into_iter()
+            .map(|n| NotificationInfo {
+                last_delivery: deliveries
+                    .iter()
+                    .rev()
+                    .find(|d| d.kind == Kind::Email && d.target == n.email)
+                    .cloned(),
+                notification: n,
+            })
+            .collect())
+    }
+    pub fn print_notifications(&self, format: Format) -> DResult<()> {
+        let notifications = self.notification_infos()?;
+        if !format.is_human() {
+            return output::print_list(format, &notifications);
+        }
+        for info in notifications {
+            let n = info.notification;
+            let mut line = format!("{} [{}]", n.email.yellow(), n.branches.join(", "));
+            match info.last_delivery {
+                Some(d) if d.status == Status::Delivered => line.push_str(&format!(
+                    ", last delivery: {} {}",
+                    "ok".green(),
+                    time::ago(d.last_attempt.unwrap_or(d.created))
+                )),
+                Some(d) => line.push_str(&format!(
+                    ", last delivery: {} ({}) {}",
+                    d.status.to_string().red(),
+                    d.error.unwrap_or_default(),
+                    time::ago(d.last_attempt.unwrap_or(d.created))
+                )),
+                None => {}
+            }
+            println!("{}", line);
+        }
+        Ok(())
+    }
     pub fn set(&self, param: &str, value: &str) -> DResult<()> {
         self.exists()?;
         let mut config_path = self.path.clone();
         config_path.push("config");
-        sh(&format!(
-            r#"git config -f "{}" {} "{}""#,
-            config_path.to_string_lossy(),
-            param,
-            value
-        ))?;
+        cmd!("git", "config", "-f", &config_path, param, value)
+            .mutating()
+            .run()?;
         chmod(&config_path, 0o644)?;
         Ok(())
     }
@@ -135,25 +782,201 @@ impl Repository {
         self.exists()?;
         let mut config_path = self.path.clone();
         config_path.push("config");
-        sh(&format!(
-            r#"git config -f "{}" --unset {}"#,
-            config_path.to_string_lossy(),
+        cmd!("git", "config", "-f", &config_path, "--unset", param)
+            .mutating()
+            .run()?;
+        chmod(&config_path, 0o644)?;
+        Ok(())
+    }
+    /// Adds a value to a multi-valued config key
+    pub fn add(&self, param: &str, value: &str) -> DResult<()> {
+        self.exists()?;
+        let config_path = self.path.join("config");
+        cmd!("git", "config", "-f", &config_path, "--add", param, value)
+            .mutating()
+            .run()?;
+        chmod(&config_path, 0o644)?;
+        Ok(())
+    }
+    /// Removes a value of a multi-valued config key
+    pub fn unset_value(&self, param: &str, value: &str) -> DResult<()> {
+        self.exists()?;
+        let config_path = self.path.join("config");
+        cmd!(
+            "git",
+            "config",
+            "-f",
+            &config_path,
+            "--fixed-value",
+            "--unset-all",
             param,
-        ))?;
+            value
+        )
+        .mutating()
+        .run()?;
         chmod(&config_path, 0o644)?;
         Ok(())
     }
+    /// Removes the config section with all its keys
+    pub fn remove_section(&self, section: &str) -> DResult<()> {
+        self.exists()?;
+        let config_path = self.path.join("config");
+        cmd!(
+            "git",
+            "config",
+            "-f",
+            &config_path,
+            "--remove-section",
+            section
+        )
+        .mutating()
+        .run()?;
+        chmod(&config_path, 0o644)?;
+        Ok(())
+    }
+    /// Sets or unsets a boolean config flag, the config is written only if changed
+    pub fn set_flag(&self, param: &str, value: bool) -> DResult<()> {
+        if self.read_config()?.get_bool(param) == value {
+            return Ok(());
+        }
+        if value {
+            self.set(param, "true")
+        } else {
+            self.unset(param)
+        }
+    }
+    /// Teams which give the user access to the repository
+    pub fn user_teams(&self, login: &str) -> DResult<Vec<Team>> {
+        let system = system();
+        let mut result = Vec::new();
+        for name in self.read_config()?.teams() {
+            let team: Team = name.parse()?;
+            if system
+                .group_members(team.group())?
+                .iter()
+                .any(|m| m == login)
+            {
+                result.push(team);
+            }
+        }
+        Ok(result)
+    }
+    /// Namespaces which give the user access to the repository
+    pub fn user_namespaces(&self, login: &str) -> DResult<Vec<Namespace>> {
+        let mut result = Vec::new();
+        for ns in Namespace::for_repo(self)? {
+            if ns.users()?.iter().any(|u| u == login) {
+                result.push(ns);
+            }
+        }
+        Ok(result)
+    }
+    /// Users granted access directly. Members without teams and namespaces are direct as well,
+    /// as they were granted before these have been introduced
+    pub fn direct_users(&self) -> DResult<Vec<User>> {
+        let direct = self.read_config()?.direct_users();
+        let mut result = Vec::new();
+        for user in self.users()? {
+            if direct.iter().any(|d| d == user.login())
+                || (self.user_teams(user.login())?.is_empty()
+                    && self.user_namespaces(user.login())?.is_empty())
+            {
+                result.push(user);
+            }
+        }
+        Ok(result)
+    }
+    /// Repository-wide maintainers set directly. Maintainers not set by namespaces are direct as
+    /// well, as they were set before namespaces have been introduced
+    pub fn direct_maintainers(&self) -> DResult<Vec<String>> {
+        let config = self.read_config()?;
+        let direct = config.direct_maintainers();
+        let mut by_namespaces = BTreeSet::new();
+        for ns in Namespace::for_repo(self)? {
+            by_namespaces.extend(ns.maintainers()?);
+        }
+        Ok(config
+            .maintainers()
+            .into_iter()
+            .filter(|login| direct.contains(login) || !by_namespaces.contains(login))
+            .collect())
+    }
+    /// Archives the repository: the users are revoked, the group is deleted and the repository
+    /// is accessible by the git user only. The former members are recorded in the config
     pub fn archive(&self) -> DResult<()> {
         self.exists()?;
-        sh(&format!("groupdel {}", self.group()))?;
+        if self.read_config()?.is_archived() {
+            return failed!(format!("repository is already archived: {}", self.name));
+        }
+        let users = self.release()?;
+        self.set_flag("gmg.archived", true)?;
+        self.set("gmg.archive.time", &time::now().to_string())?;
+        for user in &users {
+            self.add("gmg.archive.member", user.login())?;
+        }
         chmod(self.path(), 0o700)?;
+        for user in users {
+            user.update_cgit()?;
+        }
         println!("Repository archived: {}", self.name_colored());
         Ok(())
     }
+    /// Recreates the group and grants access to the former members which still exist. Archived
+    /// repositories of older versions have no members recorded
+    pub fn unarchive(&self) -> DResult<()> {
+        self.exists()?;
+        let config = self.read_config()?;
+        if !config.is_archived() && system().group_exists(&self.group()?)? {
+            return failed!(format!("repository is not archived: {}", self.name));
+        }
+        self.recreate_group()?;
+        self.fix(false)?;
+        self.attach_former(&config.archived_members())?;
+        if config.is_archived() {
+            self.unset("gmg.archived")?;
+            self.remove_section("gmg.archive")?;
+        }
+        Namespace::apply_inherited(self)?;
+        println!("Repository unarchived: {}", self.name_colored());
+        Ok(())
+    }
+    /// Creates the group of a repository which has lost it (archived or trashed). If the group
+    /// has been taken by another repository meanwhile, a new one is allocated
+    pub fn recreate_group(&self) -> DResult<()> {
+        let system = system();
+        let mut group = self.group()?;
+        let mut taken = false;
+        for repo in Self::list()? {
+            if repo.name != self.name && repo.group()? == group {
+                taken = true;
+                break;
+            }
+        }
+        if taken {
+            group = self.allocate_group()?;
+            self.set("gmg.group", &group)?;
+        }
+        if !system.group_exists(&group)? {
+            system.group_add(&group)?;
+        }
+        Ok(())
+    }
+    /// Grants access to the former users, missing users are skipped
+    pub fn attach_former(&self, logins: &[String]) -> DResult<()> {
+        for login in logins {
+            let user: User = login.parse()?;
+            if user.exists().is_err() {
+                err!(format!("user {} does not exist any more, skipped", login));
+                continue;
+            }
+            user.attach(self)?;
+            user.update_cgit()?;
+        }
+        Ok(())
+    }
     pub fn branches(&self) -> DResult<Vec<String>> {
         self.exists()?;
-        self.chdir()?;
-        let out = sh("git branch")?;
+        let out = cmd!("git", "branch").dir(self.path()).run()?.stdout;
         let mut result = Vec::new();
         for line in out.lines() {
             let name = line.strip_prefix('*').unwrap_or(line);
@@ -164,8 +987,7 @@ impl Repository {
     }
     pub fn check(&self) -> DResult<()> {
         self.exists()?;
-        self.chdir()?;
-        if let Err(e) = sh("git fsck") {
+        if let Err(e) = cmd!("git", "fsck").dir(self.path()).run() {
             err!(format!(
                 "Repository {} failed to check\n{}\n",
                 self.name(),
@@ -176,65 +998,80 @@ impl Repository {
     }
     pub fn cleanup(&self) -> DResult<()> {
         self.exists()?;
-        self.chdir()?;
-        sh("git reflog expire --expire=now --all")?;
-        sh("git gc --prune=now")?;
+        cmd!("git", "reflog", "expire", "--expire=now", "--all")
+            .dir(self.path())
+            .mutating()
+            .run()?;
+        cmd!("git", "gc", "--prune=now")
+            .dir(self.path())
+            .mutating()
+            .run()?;
         self.fix(false)?;
         Ok(())
     }
+    /// Fixes permissions and installs the hook shims
     pub fn fix(&self, full: bool) -> DResult<()> {
-        sh(&format!(
-            r#"find {} -type d -exec chmod -R 002775 {{}} \;"#,
-            self.path_as_str()
-        ))?;
-        sh(&format!(
-            r#"find {} -type f -exec chmod -R 000664 {{}} \;"#,
-            self.path_as_str()
-        ))?;
+        hook::install(self.path())?;
+        // bare repositories have no reflogs by default
+        self.set_flag("core.logallrefupdates", true)?;
+        // data written by hooks (push log, deliveries)
+        ops::create_dir_all(self.path().join("gmg"))?;
+        if !dry_run() {
+            // spooled records are owned by the pushers until moved to the log
+            audit::collect(self.path())?;
+        }
+        chmod_tree(self.path(), 0o2775, 0o664)?;
         chmod(self.path(), 0o2770)?;
-        sh(&format!(
-            r#"chmod -R 000755 "{}/hooks""#,
-            self.path_as_str()
-        ))?;
-        sh(&format!(
-            r#"chown -R "{}:{}" "{}""#,
-            GIT_USER,
-            self.group(),
-            self.path_as_str()
-        ))?;
-        sh(&format!(
-            r#"chown "root:{}" "{}/config""#,
-            GIT_USER,
-            self.path_as_str()
-        ))?;
-        sh(&format!(
-            r#"chown "root:{}" "{}/description""#,
-            GIT_USER,
-            self.path_as_str()
-        ))?;
-        let mut path = self.path.clone();
-        path.push("config");
-        chmod(path, 0o000_644)?;
-        let mut path = self.path.clone();
-        path.push("description");
-        chmod(path, 0o000_644)?;
+        audit::set_permissions(self.path())?;
+        let mut hooks_path = self.path.clone();
+        hooks_path.push("hooks");
+        chmod_tree(hooks_path, 0o755, 0o755)?;
+        let git_user = &config().git_user;
+        let system = system();
+        system.chown(self.path(), git_user, Some(&self.group()?), true)?;
+        let mut config_path = self.path.clone();
+        config_path.push("config");
+        let mut description_path = self.path.clone();
+        description_path.push("description");
+        for path in [config_path, description_path] {
+            system.chown(&path, "root", Some(git_user), false)?;
+            chmod(path, 0o000_644)?;
+        }
         if full {
             self.cleanup()?;
         }
         Ok(())
     }
+    /// Creates the repository, grants of the namespaces it belongs to are applied
     pub fn create(&self, init_only: bool, description: Option<&str>) -> DResult<()> {
+        self.init(init_only, description)?;
+        Namespace::apply_inherited(self)
+    }
+    fn init(&self, init_only: bool, description: Option<&str>) -> DResult<()> {
         if self.exists().is_ok() {
-            return failed!("repository already exists".to_owned());
-        }
-        fs::create_dir_all(self.path())?;
-        sh(&format!("groupadd {}", self.group()))?;
-        set_current_dir(&*GIT_PATH)?;
-        sh(&format!(
-            r#"git init -q -b "{}" --bare --shared=group "{}.git""#,
-            MAIN_BRANCH,
-            self.name()
-        ))?;
+            return failed!(
+                AlreadyExists,
+                format!("repository already exists: {}", self.name)
+            );
+        }
+        let system = system();
+        let group = self.allocate_group()?;
+        let config = config();
+        ops::create_dir_all(self.path())?;
+        system.group_add(&group)?;
+        cmd!(
+            "git",
+            "init",
+            "-q",
+            "-b",
+            &config.main_branch,
+            "--bare",
+            "--shared=group",
+            self.path()
+        )
+        .mutating()
+        .run()?;
+        self.set("gmg.group", &group)?;
         self.fix(false)?;
         self.set("gmg.version", VERSION)?;
         self.set("receive.denyNonFastForwards", "false")?;
@@ -244,60 +1081,102 @@ impl Repository {
         }
         self.do_initial_commit()?;
         self.set_description(description)?;
-        for branch in PROTECTED_BRANCHES {
+        for branch in &config.protected_branches {
             self.protect(branch)?;
         }
         println!("Repository created: {}", self.name_colored());
         Ok(())
     }
     fn do_initial_commit(&self) -> DResult<()> {
+        if dry_run() {
+            ops::report(format!("push initial commit to {}", self.name()));
+            return Ok(());
+        }
         let dir = TempDir::new("gmg")?;
-        set_current_dir(dir.path())?;
-        sh(&format!(
-            r#"git clone --quiet "{}" > /dev/null 2>&1"#,
-            self.path_as_str()
-        ))?;
+        cmd!("git", "clone", "--quiet", self.path())
+            .dir(dir.path())
+            .run()?;
         let short_name = self.short_name();
-        set_current_dir(short_name)?;
-        fs::write("README.md", format!("# {}", short_name))?;
-        sh("git add README.md")?;
-        sh("git commit --quiet -a -m init")?;
-        sh(&format!(r#"git push --quiet origin "{}""#, MAIN_BRANCH))?;
+        let mut work_dir = dir.path().to_owned();
+        work_dir.push(short_name);
+        let mut readme = work_dir.clone();
+        readme.push("README.md");
+        fs::write(readme, format!("# {}", short_name))?;
+        cmd!("git", "add", "README.md").dir(&work_dir).run()?;
+        cmd!("git", "commit", "--quiet", "-a", "-m", "init")
+            .dir(&work_dir)
+            .run()?;
+        cmd!("git", "push", "--quiet", "origin", &config().main_branch)
+            .dir(&work_dir)
+            .run()?;
         dir.close()?;
-        self.chdir()?;
         Ok(())
     }
-    pub fn destroy(&self) -> DResult<()> {
-        self.exists()?;
-        for user in self.users()? {
-            user.revoke(self)?;
+    /// Revokes all users and deletes the group, returns the former users (users planned to be
+    /// deleted in dry-run mode are skipped)
+    fn release(&self) -> DResult<Vec<User>> {
+        let mut users = self.users()?;
+        for user in &users {
+            user.detach(self)?;
         }
-        sh(&format!(r#"groupdel "{}""#, self.group()))?;
-        fs::remove_dir_all(self.path())?;
-        set_current_dir(&*GIT_PATH)?;
+        system().group_del(&self.group()?)?;
+        users.retain(|u| u.exists().is_ok());
+        Ok(users)
+    }
+    fn remove_empty_parents(&self) {
         let mut sp = self.name.split('/');
         let top_dir = sp.next().unwrap();
         if sp.next().is_some() {
-            sh_any(&format!(
-                r#"find "{}" -type d -not -path "*/*.git/*" -not -path "*/*.git" -exec rmdir {{}} \; > /dev/null 2>&1"#,
-                top_dir
-            ))?;
-            let _r = fs::remove_dir(top_dir);
+            let mut path = config().git_path.clone();
+            path.push(top_dir);
+            ops::remove_empty_dirs(path);
+        }
+    }
+    /// Moves the repository to the trash, it can be restored with the former users
+    pub fn destroy(&self) -> DResult<()> {
+        self.exists()?;
+        let entry = TrashEntry::new(self)?;
+        let users = self.release()?;
+        trash::put(&entry, self.path())?;
+        self.remove_empty_parents();
+        for user in users {
+            user.update_cgit()?;
         }
         println!(
-            "Repository {}: {}",
+            "Repository {}: {} (moved to trash as {})",
             "destroyed".red().bold(),
-            self.name_colored()
+            self.name_colored(),
+            entry.id
         );
         Ok(())
     }
+    /// Removes the repository permanently
+    fn remove(&self) -> DResult<()> {
+        self.exists()?;
+        let users = self.release()?;
+        ops::remove_dir_all(self.path())?;
+        self.remove_empty_parents();
+        for user in users {
+            user.update_cgit()?;
+        }
+        Ok(())
+    }
     pub fn rename(&self, new_repo: &Repository) -> DResult<()> {
         self.exists()?;
-        new_repo.create(true, None)?;
+        new_repo.init(true, None)?;
         match self.replace_and_move(new_repo) {
-            Ok(()) => self.destroy(),
+            Ok(()) => {
+                self.remove()?;
+                println!(
+                    "Repository {}: {} -> {}",
+                    "renamed".green().bold(),
+                    self.name_colored(),
+                    new_repo.name_colored()
+                );
+                Ok(())
+            }
             Err(e) => {
-                if let Err(err_des) = new_repo.destroy() {
+                if let Err(err_des) = new_repo.remove() {
                     err!(err_des.to_string());
                 }
                 Err(e)
@@ -305,42 +1184,82 @@ impl Repository {
         }
     }
     fn replace_and_move(&self, target: &Repository) -> DResult<()> {
-        if target.path_as_str().len() + 2 < GIT_PATH.to_string_lossy().len() {
+        if target.path_as_str().len() + 2 < config().git_path.to_string_lossy().len() {
             return failed!("invalid repo path".to_owned());
         }
-        sh(&format!("rm -rf {}/*", target.path_as_str()))?;
-        sh(&format!(
-            "cp -prf {}/* {}/",
-            self.path_as_str(),
-            target.path_as_str()
-        ))?;
+        let group = target.group()?;
+        ops::clear_dir(target.path())?;
+        ops::copy_dir_contents(self.path(), target.path())?;
+        // the copied config contains the source group
+        target.set("gmg.group", &group)?;
         target.fix(false)?;
+        // access flags are in the copied config already
         for user in self.users()? {
-            user.grant(target)?;
+            user.attach(target)?;
+            user.update_cgit()?;
         }
-        Ok(())
+        Namespace::apply_inherited(target)
     }
-    pub fn print_all(short: bool) -> DResult<()> {
-        set_current_dir(&*GIT_PATH)?;
-        let out = sh(r#"find . -name "*.git" -type d"#)?;
-        let mut result = Vec::new();
-        for line in out.lines() {
-            if let Some(l) = line.strip_prefix("./") {
-                if let Some(n) = l.strip_suffix(".git") {
-                    result.push(n);
+    /// Lists all repositories in the git directory
+    pub fn list() -> DResult<Vec<Repository>> {
+        fn find_repos(dir: &Path, prefix: &str, result: &mut Vec<String>) -> DResult<()> {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                if !entry.file_type()?.is_dir() {
+                    continue;
+                }
+                let file_name = entry.file_name();
+                let name = file_name.to_string_lossy();
+                if name.starts_with('.') {
+                    continue;
+                }
+                if let Some(n) = name.strip_suffix(".git") {
+                    result.push(format!("{}{}", prefix, n));
+                } else {
+                    find_repos(&entry.path(), &format!("{}{}/", prefix, name), result)?;
                 }
             }
+            Ok(())
         }
-        result.sort_unstable();
-        for r in result {
-            let repo = r.parse::<Repository>()?;
+        let mut names = Vec::new();
+        find_repos(&config().git_path, "", &mut names)?;
+        names.sort_unstable();
+        let mut result = Vec::new();
+        for name in names {
+            result.push(name.parse::<Repository>()?);
+        }
+        Ok(result)
+    }
+    /// Lists repositories, only archived (archived = Some(true)) or active (Some(false)) ones if
+    /// specified
+    pub fn print_all(short: bool, archived: Option<bool>, format: Format) -> DResult<()> {
+        let mut repos = Vec::new();
+        for repo in Self::list()? {
+            let is_archived = repo.read_config()?.is_archived();
+            if archived.is_none_or(|a| a == is_archived) {
+                repos.push((repo, is_archived));
+            }
+        }
+        if !format.is_human() {
+            let mut result = Vec::new();
+            for (repo, _) in repos {
+                result.push(repo.info()?);
+            }
+            return output::print_list(format, &result);
+        }
+        for (repo, is_archived) in repos {
             if short {
                 println!("{}", repo.name_colored());
             } else {
                 println!(
-                    "{} ({})",
+                    "{} ({}){}",
                     repo.name_colored(),
-                    repo.read_description()?.unwrap_or_default()
+                    repo.read_description()?.unwrap_or_default(),
+                    if is_archived {
+                        format!(" {}", "[archived]".magenta())
+                    } else {
+                        String::new()
+                    }
                 );
             }
         }
@@ -348,77 +1267,257 @@ impl Repository {
     }
     pub fn users(&self) -> DResult<Vec<User>> {
         self.exists()?;
-        let out = sh_any(&format!(r#"grep "^{}:" /etc/group"#, self.group()))?;
         let mut users = Vec::new();
-        for line in out.lines() {
-            for user in line.split(':').nth(3).unwrap().split(',') {
-                if !user.is_empty() {
-                    users.push(user.parse()?);
-                }
-            }
+        for login in system().group_members(&self.group()?)? {
+            users.push(login.parse()?);
         }
         users.sort();
         Ok(users)
     }
-    pub fn print_info(&self) -> DResult<()> {
+    pub fn info(&self) -> DResult<RepoInfo> {
         self.exists()?;
-        self.chdir()?;
-        let config = self.load_config()?;
-        let mut protected_branches = Vec::new();
-        let mut maintainers = Vec::new();
-        for section in config.sections() {
-            if let Some(hooks_section) = section.strip_prefix("hooks") {
-                let name = hooks_section.trim();
-                if name.len() > 2 {
-                    let sn = &name[1..name.len() - 1];
-                    let mut sp = sn.splitn(2, '.');
-                    match sp.next().unwrap() {
-                        "branch" => {
-                            if let Some(branch) = sp.next() {
-                                if let Some(p) = config.get(&section, "protected") {
-                                    if p == "true" {
-                                        protected_branches.push(branch.to_owned());
-                                    }
-                                }
-                            }
-                        }
-                        "user" => {
-                            if let Some(login) = sp.next() {
-                                if let Some(p) = config.get(&section, "maintainer") {
-                                    if p == "true" {
-                                        maintainers.push(login.to_owned());
-                                    }
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
+        let config = self.read_config()?;
+        Ok(RepoInfo {
+            name: self.name.clone(),
+            group: self.group()?,
+            path: self.path_as_str().to_string(),
+            description: self.read_description()?,
+            branches: self.branches()?,
+            protected_branches: config.protected_branches(),
+            protected_tags: config.protected_tags(),
+            users: self.users()?.iter().map(|u| u.login().to_owned()).collect(),
+            read_only_users: config.read_only_users(),
+            maintainers: config.maintainers(),
+            branch_maintainers: config.branch_maintainers(),
+            rci: config.rci(),
+            archived: config.is_archived(),
+            archived_at: config.archived_at(),
+            archived_members: config.archived_members(),
+        })
+    }
+    pub fn print_branches(&self, format: Format) -> DResult<()> {
+        let branches = self.branches()?;
+        if format.is_human() {
+            for r in branches {
+                println!("{}", r.yellow());
+            }
+            return Ok(());
+        }
+        let config = self.read_config()?;
+        let result: Vec<BranchInfo> = branches
+            .into_iter()
+            .map(|name| {
+                let rule = config.protection_rule(&name);
+                BranchInfo {
+                    protected: rule.is_some(),
+                    rule,
+                    name,
+                }
+            })
+            .collect();
+        output::print_list(format, &result)
+    }
+    /// Shows which protection rule matches the branch (or the tag if tag = true), the branch may
+    /// not exist yet
+    pub fn print_protection(&self, name: &str, tag: bool, format: Format) -> DResult<()> {
+        self.exists()?;
+        let config = self.read_config()?;
+        let rule = if tag {
+            config.tag_protection_rule(name)
+        } else {
+            config.protection_rule(name)
+        };
+        if !format.is_human() {
+            let info = BranchInfo {
+                name: name.to_owned(),
+                protected: rule.is_some(),
+                rule,
+            };
+            return output::print_item(format, &info);
+        }
+        match rule {
+            Some(rule) if rule == name => {
+                println!("{}: {}", name.yellow(), "protected".green().bold());
+            }
+            Some(rule) => println!(
+                "{}: {} by {}",
+                name.yellow(),
+                "protected".green().bold(),
+                rule.green()
+            ),
+            None => println!("{}: not protected", name.yellow()),
+        }
+        Ok(())
+    }
+    pub fn user_infos(&self) -> DResult<Vec<RepoUserInfo>> {
+        let config = self.read_config()?;
+        let read_only = config.read_only_users();
+        let maintainers = config.maintainers();
+        let direct: Vec<String> = self
+            .direct_users()?
+            .iter()
+            .map(|u| u.login().to_owned())
+            .collect();
+        let mut result = Vec::new();
+        for user in self.users()? {
+            let login = user.login().to_owned();
+            result.push(RepoUserInfo {
+                access: Access::from_read_only(read_only.contains(&login)),
+                maintainer: maintainers.contains(&login),
+                direct: direct.contains(&login),
+                teams: self
+                    .user_teams(&login)?
+                    .iter()
+                    .map(|t| t.name().to_owned())
+                    .collect(),
+                namespaces: self
+                    .user_namespaces(&login)?
+                    .iter()
+                    .map(|ns| ns.name().to_owned())
+                    .collect(),
+                login,
+            });
+        }
+        Ok(result)
+    }
+    pub fn print_users(&self, format: Format) -> DResult<()> {
+        let users = self.user_infos()?;
+        if !format.is_human() {
+            return output::print_list(format, &users);
+        }
+        for user in users {
+            let mut line = user.login.yellow().to_string();
+            if user.access == Access::Read {
+                line.push_str(" (read-only)");
+            }
+            let mut sources = Vec::new();
+            if !user.teams.is_empty() {
+                sources.push(format!("teams: {}", user.teams.join(", ")));
+            }
+            if !user.namespaces.is_empty() {
+                sources.push(format!("namespaces: {}", user.namespaces.join(", ")));
+            }
+            if !sources.is_empty() {
+                if user.direct {
+                    sources.insert(0, "direct".to_owned());
                 }
+                line.push_str(&format!(" [{}]", sources.join(", ")));
             }
+            println!("{}", line);
         }
-        protected_branches.sort();
-        maintainers.sort();
+        Ok(())
+    }
+    pub fn print_info(&self, format: Format) -> DResult<()> {
+        let info = self.info()?;
+        if !format.is_human() {
+            return output::print_item(format, &info);
+        }
+        let RepoInfo {
+            description,
+            branches,
+            protected_branches,
+            protected_tags,
+            users,
+            read_only_users,
+            maintainers,
+            branch_maintainers,
+            archived,
+            archived_at,
+            archived_members,
+            ..
+        } = info;
         println!("name: {}", self.name_colored());
-        if let Some(desc) = self.read_description()? {
+        if let Some(desc) = description {
             println!("description: {}", desc);
         }
+        if archived {
+            println!(
+                "archived: {}",
+                archived_at
+                    .map(time::format_utc)
+                    .unwrap_or_default()
+                    .magenta()
+            );
+            println!("archived members:");
+            for u in archived_members {
+                println!(" {}", u.yellow());
+            }
+        }
         println!("path: {}", self.path_as_str().white());
+        let config = self.read_config()?;
         println!("branches:");
-        for r in self.branches()? {
-            println!(" {}", r.yellow());
+        for r in branches {
+            match config.protection_rule(&r) {
+                Some(rule) if rule == r => println!(" {} (protected)", r.yellow()),
+                Some(rule) => println!(" {} (protected by {})", r.yellow(), rule.green()),
+                None => println!(" {}", r.yellow()),
+            }
         }
         println!("protected branches:");
         for r in protected_branches {
             println!(" {}", r.green());
         }
+        println!("protected tags:");
+        for r in protected_tags {
+            println!(" {}", r.green());
+        }
         println!("users:");
-        for u in self.users()? {
-            println!(" {}", u.login_colored());
+        for u in users {
+            if read_only_users.contains(&u) {
+                println!(" {} (read-only)", u.yellow());
+            } else {
+                println!(" {}", u.yellow());
+            }
         }
         println!("maintainers:");
         for r in maintainers {
             println!(" {}", r.green());
         }
+        for m in branch_maintainers {
+            println!(" {} ({})", m.login.green(), m.branch.yellow());
+        }
+        Ok(())
+    }
+    pub fn maintainer_infos(&self) -> DResult<Vec<MaintainerInfo>> {
+        self.exists()?;
+        let config = self.read_config()?;
+        let mut result: BTreeMap<String, MaintainerInfo> = BTreeMap::new();
+        for login in config.maintainers() {
+            result.insert(
+                login.clone(),
+                MaintainerInfo {
+                    login,
+                    all_branches: true,
+                    branches: Vec::new(),
+                },
+            );
+        }
+        for m in config.branch_maintainers() {
+            result
+                .entry(m.login.clone())
+                .or_insert_with(|| MaintainerInfo {
+                    login: m.login,
+                    all_branches: false,
+                    branches: Vec::new(),
+                })
+                .branches
+                .push(m.branch);
+        }
+        Ok(result.into_values().collect())
+    }
+    pub fn print_maintainers(&self, format: Format) -> DResult<()> {
+        let maintainers = self.maintainer_infos()?;
+        if !format.is_human() {
+            return output::print_list(format, &maintainers);
+        }
+        for m in maintainers {
+            let mut scopes = Vec::new();
+            if m.all_branches {
+                scopes.push("all branches".to_owned());
+            }
+            scopes.extend(m.branches.iter().map(|b| b.yellow().to_string()));
+            println!("{} ({})", m.login.green(), scopes.join(", "));
+        }
         Ok(())
     }
 }
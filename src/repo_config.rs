@@ -0,0 +1,358 @@
+use crate::{cmd, dry_run, DResult};
+use serde::Serialize;
+use std::path::Path;
+
+/// Repository config (hooks and gmg sections), as seen by git
+#[derive(Default)]
+pub struct RepoConfig {
+    values: Vec<(String, String)>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Rci {
+    pub branch: String,
+    pub url: String,
+    #[serde(skip)]
+    pub secret: String,
+}
+
+/// A generic HTTP endpoint notified on pushes, stored as hooks.webhook.<name>.* keys
+#[derive(Serialize, Clone)]
+pub struct Webhook {
+    pub name: String,
+    pub url: String,
+    #[serde(skip)]
+    pub secret: Option<String>,
+    /// branch names, full ref names (refs/...) or glob patterns, all refs if empty
+    pub refs: Vec<String>,
+    /// delivery timeout, in seconds
+    pub timeout: u32,
+}
+
+pub const DEFAULT_WEBHOOK_TIMEOUT: u32 = 10;
+
+impl Webhook {
+    /// Checks if the webhook is fired for the ref
+    pub fn matches(&self, refname: &str) -> bool {
+        self.refs.is_empty() || self.refs.iter().any(|rule| ref_matches(rule, refname))
+    }
+}
+
+/// An email push notification recipient, stored as multi-valued hooks.notify.<email>.branch
+#[derive(Serialize, Clone)]
+pub struct Notification {
+    pub email: String,
+    /// branch names or glob patterns ("*" for all branches)
+    pub branches: Vec<String>,
+}
+
+impl Notification {
+    /// Checks if the recipient is notified on pushes to the ref
+    pub fn matches(&self, refname: &str) -> bool {
+        self.branches.iter().any(|rule| ref_matches(rule, refname))
+    }
+}
+
+/// A maintainer scoped to a branch or a branch glob pattern
+#[derive(Serialize, Clone)]
+pub struct BranchMaintainer {
+    pub login: String,
+    pub branch: String,
+}
+
+fn parse_bool(value: &str) -> bool {
+    matches!(
+        value.to_lowercase().as_str(),
+        "true" | "yes" | "on" | "1" | ""
+    )
+}
+
+/// Checks if the name is a glob pattern
+pub fn is_pattern(name: &str) -> bool {
+    name.contains(['*', '?', '['])
+}
+
+/// Matches the text with a glob pattern, as bash does for [[ text == pattern ]]: * matches any
+/// sequence (including /), ? any single char, [...] a char class ([!...] or [^...] negated)
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    fn class(class: &[char], c: char) -> Option<(bool, usize)> {
+        let (negated, start) = match class.first() {
+            Some('!' | '^') => (true, 1),
+            _ => (false, 0),
+        };
+        let mut i = start;
+        let mut matched = false;
+        while i < class.len() {
+            if class[i] == ']' && i > start {
+                return Some((matched != negated, i + 1));
+            }
+            if i + 2 < class.len() && class[i + 1] == '-' && class[i + 2] != ']' {
+                matched |= class[i] <= c && c <= class[i + 2];
+                i += 3;
+            } else {
+                matched |= class[i] == c;
+                i += 1;
+            }
+        }
+        None
+    }
+    fn matches(p: &[char], t: &[char]) -> bool {
+        match p.first() {
+            None => t.is_empty(),
+            Some('*') => (0..=t.len()).any(|i| matches(&p[1..], &t[i..])),
+            Some('?') => !t.is_empty() && matches(&p[1..], &t[1..]),
+            Some('[') => {
+                let Some(&c) = t.first() else {
+                    return false;
+                };
+                match class(&p[1..], c) {
+                    Some((true, len)) => matches(&p[1 + len..], &t[1..]),
+                    Some((false, _)) => false,
+                    // an unterminated class is matched literally
+                    None => c == '[' && matches(&p[1..], &t[1..]),
+                }
+            }
+            Some(&pc) => t.first() == Some(&pc) && matches(&p[1..], &t[1..]),
+        }
+    }
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    matches(&p, &t)
+}
+
+/// Matches the full ref name with a rule: a branch name, a full ref name (refs/...) or a glob
+/// pattern of them. Rules without refs/ prefix match branches only
+pub fn ref_matches(rule: &str, refname: &str) -> bool {
+    let name = if rule.starts_with("refs/") {
+        refname
+    } else if let Some(branch) = refname.strip_prefix("refs/heads/") {
+        branch
+    } else {
+        return false;
+    };
+    rule == name || (is_pattern(rule) && glob_match(rule, name))
+}
+
+impl RepoConfig {
+    /// A missing file (e.g. of a repository planned in dry-run mode) is loaded as empty
+    pub fn load(path: &Path) -> DResult<Self> {
+        if dry_run() && !path.exists() {
+            return Ok(Self { values: Vec::new() });
+        }
+        let out = cmd!("git", "config", "-f", path, "-z", "--list")
+            .run()?
+            .stdout;
+        let mut values = Vec::new();
+        for entry in out.split('\0').filter(|e| !e.is_empty()) {
+            let (key, value) = entry.split_once('\n').unwrap_or((entry, ""));
+            values.push((key.to_owned(), value.to_owned()));
+        }
+        Ok(Self { values })
+    }
+    /// All the entries (lowercased key names, except subsections), in the config order
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.values
+    }
+    /// Returns the last value of the key, as git does
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+    /// Returns all values of a multi-valued key
+    pub fn get_all(&self, key: &str) -> Vec<String> {
+        self.values
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+            .collect()
+    }
+    pub fn get_bool(&self, key: &str) -> bool {
+        self.get(key).is_some_and(parse_bool)
+    }
+    /// Returns subsections of the section which have the variable set, e.g. for
+    /// hooks.branch.main.protected: section = "hooks", prefix = "branch.", name = "protected"
+    /// returns "main"
+    fn subsections<'a>(
+        &'a self,
+        section: &'a str,
+        prefix: &'a str,
+        name: &'a str,
+    ) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        self.values.iter().filter_map(move |(k, v)| {
+            k.strip_prefix(section)?
+                .strip_prefix('.')?
+                .strip_prefix(prefix)?
+                .strip_suffix(name)?
+                .strip_suffix('.')
+                .map(|sub| (sub, v.as_str()))
+        })
+    }
+    /// Returns sorted subsections of the section which have the variable set
+    pub fn subsection_names(&self, section: &str, name: &str) -> Vec<String> {
+        let mut result: Vec<String> = self
+            .subsections(section, "", name)
+            .map(|(sub, _)| sub.to_owned())
+            .collect();
+        result.sort();
+        result.dedup();
+        result
+    }
+    fn rules(&self, kind: &str) -> Vec<String> {
+        let mut result: Vec<String> = self
+            .subsections("hooks", kind, "protected")
+            .filter(|(name, _)| self.get_bool(&format!("hooks.{}{}.protected", kind, name)))
+            .map(|(name, _)| name.to_owned())
+            .collect();
+        result.sort();
+        result.dedup();
+        result
+    }
+    /// Returns the exact name or the first matching pattern
+    fn matching_rule(rules: Vec<String>, name: &str) -> Option<String> {
+        if rules.iter().any(|r| r == name) {
+            return Some(name.to_owned());
+        }
+        rules
+            .into_iter()
+            .find(|r| is_pattern(r) && glob_match(r, name))
+    }
+    /// Protection rules: branch names and glob patterns
+    pub fn protected_branches(&self) -> Vec<String> {
+        self.rules("branch.")
+    }
+    /// Returns the protection rule of the branch
+    pub fn protection_rule(&self, branch: &str) -> Option<String> {
+        Self::matching_rule(self.protected_branches(), branch)
+    }
+    /// Tag protection rules: tag names and glob patterns, matching tags are immutable and can be
+    /// deleted by maintainers only
+    pub fn protected_tags(&self) -> Vec<String> {
+        self.rules("tag.")
+    }
+    pub fn tag_protection_rule(&self, tag: &str) -> Option<String> {
+        Self::matching_rule(self.protected_tags(), tag)
+    }
+    /// Returns subsections which have the boolean flag set, e.g. for hooks.user.<login>.maintainer
+    fn flagged(&self, section: &str, prefix: &str, name: &str) -> Vec<String> {
+        let mut result: Vec<String> = self
+            .subsections(section, prefix, name)
+            .filter(|(sub, _)| self.get_bool(&format!("{}.{}{}.{}", section, prefix, sub, name)))
+            .map(|(sub, _)| sub.to_owned())
+            .collect();
+        result.sort();
+        result.dedup();
+        result
+    }
+    /// Repository-wide maintainers
+    pub fn maintainers(&self) -> Vec<String> {
+        self.flagged("hooks", "user.", "maintainer")
+    }
+    /// Branch-scoped maintainers, stored as multi-valued hooks.branch.<name|pattern>.maintainer
+    pub fn branch_maintainers(&self) -> Vec<BranchMaintainer> {
+        let mut result: Vec<BranchMaintainer> = self
+            .subsections("hooks", "branch.", "maintainer")
+            .map(|(branch, login)| BranchMaintainer {
+                login: login.to_owned(),
+                branch: branch.to_owned(),
+            })
+            .collect();
+        result.sort_by(|a, b| (&a.login, &a.branch).cmp(&(&b.login, &b.branch)));
+        result.dedup_by(|a, b| a.login == b.login && a.branch == b.branch);
+        result
+    }
+    /// Checks if the user is a repository-wide maintainer or a maintainer of the branch (by its
+    /// name or a matching pattern)
+    pub fn is_branch_maintainer(&self, login: &str, branch: &str) -> bool {
+        self.maintainers().iter().any(|m| m == login)
+            || self.branch_maintainers().iter().any(|m| {
+                m.login == login
+                    && (m.branch == branch
+                        || (is_pattern(&m.branch) && glob_match(&m.branch, branch)))
+            })
+    }
+    /// Archived repositories have no group, their former members are recorded to be granted
+    /// access again on unarchive
+    pub fn is_archived(&self) -> bool {
+        self.get_bool("gmg.archived")
+    }
+    pub fn archived_at(&self) -> Option<u64> {
+        self.get("gmg.archive.time")?.parse().ok()
+    }
+    pub fn archived_members(&self) -> Vec<String> {
+        self.get_all("gmg.archive.member")
+    }
+    /// Users who can clone but any ref update is denied by the update hook
+    pub fn read_only_users(&self) -> Vec<String> {
+        self.flagged("hooks", "user.", "readonly")
+    }
+    /// Users granted access directly, not only through teams
+    pub fn direct_users(&self) -> Vec<String> {
+        self.flagged("gmg", "user.", "direct")
+    }
+    /// Users set as repository-wide maintainers directly, not only by namespaces
+    pub fn direct_maintainers(&self) -> Vec<String> {
+        self.flagged("gmg", "user.", "directmaintainer")
+    }
+    /// Teams granted access to the repository
+    pub fn teams(&self) -> Vec<String> {
+        self.flagged("gmg", "team.", "granted")
+    }
+    pub fn webhooks(&self) -> Vec<Webhook> {
+        let mut result: Vec<Webhook> = self
+            .subsections("hooks", "webhook.", "url")
+            .map(|(name, url)| {
+                let key = |k: &str| format!("hooks.webhook.{}.{}", name, k);
+                Webhook {
+                    name: name.to_owned(),
+                    url: url.to_owned(),
+                    secret: self.get(&key("secret")).map(ToOwned::to_owned),
+                    refs: self.get_all(&key("ref")),
+                    timeout: self
+                        .get(&key("timeout"))
+                        .and_then(|t| t.parse().ok())
+                        .unwrap_or(DEFAULT_WEBHOOK_TIMEOUT),
+                }
+            })
+            .collect();
+        result.sort_by(|a, b| a.name.cmp(&b.name));
+        // the last url wins, as git does
+        result.reverse();
+        result.dedup_by(|a, b| a.name == b.name);
+        result.reverse();
+        result
+    }
+    /// Email notification recipients, sorted by address
+    pub fn notifications(&self) -> Vec<Notification> {
+        let mut emails: Vec<&str> = self
+            .subsections("hooks", "notify.", "branch")
+            .map(|(email, _)| email)
+            .collect();
+        emails.sort_unstable();
+        emails.dedup();
+        emails
+            .into_iter()
+            .map(|email| Notification {
+                email: email.to_owned(),
+                branches: self.get_all(&format!("hooks.notify.{}.branch", email)),
+            })
+            .collect()
+    }
+    pub fn rci(&self) -> Vec<Rci> {
+        let mut result: Vec<Rci> = Vec::new();
+        for (branch, url) in self.subsections("hooks", "branch.", "rci.url") {
+            if let Some(secret) = self.get(&format!("hooks.branch.{}.rci.secret", branch)) {
+                result.retain(|r| r.branch != branch);
+                result.push(Rci {
+                    branch: branch.to_owned(),
+                    url: url.to_owned(),
+                    secret: secret.to_owned(),
+                });
+            }
+        }
+        result.sort_by(|a, b| a.branch.cmp(&b.branch));
+        result
+    }
+}
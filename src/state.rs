@@ -0,0 +1,523 @@
+use crate::config::config;
+use crate::key::parse_keys;
+use crate::namespace::Namespace;
+use crate::repo::Repository;
+use crate::system::system;
+use crate::user::User;
+use crate::{failed, DResult, ErrorKind};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Desired server state: users, repositories and access to them. Objects which are not listed
+/// in the state are not managed
+#[derive(Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct State {
+    #[serde(default)]
+    pub users: BTreeMap<String, UserState>,
+    #[serde(default)]
+    pub repos: BTreeMap<String, RepoState>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserState {
+    pub name: String,
+    /// public SSH keys, if empty, keys of existing users are not managed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<String>,
+}
+
+/// Access granted directly only, team and namespace grants are managed by teams and namespaces.
+/// Branch maintainers, webhooks and email notifications are not managed
+#[derive(Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RepoState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub protected: BTreeSet<String>,
+    #[serde(default)]
+    pub users: BTreeSet<String>,
+    /// users (listed in users) with read-only access
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub read_only: BTreeSet<String>,
+    #[serde(default)]
+    pub maintainers: BTreeSet<String>,
+    /// RCI triggers by branch
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rci: BTreeMap<String, RciState>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RciState {
+    pub url: String,
+    /// if not specified, the current secret is kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+pub enum Change {
+    CreateUser {
+        user: User,
+        name: String,
+        keys: Vec<String>,
+    },
+    SetUserName {
+        user: User,
+        name: String,
+    },
+    SetUserKeys {
+        user: User,
+        keys: Vec<String>,
+    },
+    CreateRepo {
+        repo: Repository,
+        description: Option<String>,
+    },
+    SetDescription {
+        repo: Repository,
+        description: String,
+    },
+    Protect {
+        repo: Repository,
+        branch: String,
+    },
+    Unprotect {
+        repo: Repository,
+        branch: String,
+    },
+    RciSet {
+        repo: Repository,
+        branch: String,
+        url: String,
+        secret: String,
+    },
+    RciUnset {
+        repo: Repository,
+        branch: String,
+    },
+    Grant {
+        user: User,
+        repo: Repository,
+        read_only: bool,
+    },
+    Revoke {
+        user: User,
+        repo: Repository,
+    },
+    MaintainerSet {
+        user: User,
+        repo: Repository,
+    },
+    MaintainerUnset {
+        user: User,
+        repo: Repository,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (add, change, del) = ("+".green().bold(), "~".yellow().bold(), "-".red().bold());
+        match self {
+            Change::CreateUser { user, name, keys } => write!(
+                f,
+                "{} create user {} ({}), keys: {}",
+                add,
+                user.login_colored(),
+                name,
+                keys.len()
+            ),
+            Change::SetUserName { user, name } => {
+                write!(f, "{} user {} name: {}", change, user.login_colored(), name)
+            }
+            Change::SetUserKeys { user, keys } => write!(
+                f,
+                "{} user {} keys: {}",
+                change,
+                user.login_colored(),
+                keys.len()
+            ),
+            Change::CreateRepo { repo, .. } => {
+                write!(f, "{} create repository {}", add, repo.name_colored())
+            }
+            Change::SetDescription { repo, description } => write!(
+                f,
+                "{} repository {} description: {}",
+                change,
+                repo.name_colored(),
+                description
+            ),
+            Change::Protect { repo, branch } => write!(
+                f,
+                "{} protect {} branch {}",
+                add,
+                repo.name_colored(),
+                branch.yellow()
+            ),
+            Change::Unprotect { repo, branch } => write!(
+                f,
+                "{} unprotect {} branch {}",
+                del,
+                repo.name_colored(),
+                branch.yellow()
+            ),
+            Change::RciSet {
+                repo, branch, url, ..
+            } => write!(
+                f,
+                "{} RCI {} branch {}: {}",
+                add,
+                repo.name_colored(),
+                branch.yellow(),
+                url
+            ),
+            Change::RciUnset { repo, branch } => write!(
+                f,
+                "{} RCI {} branch {}",
+                del,
+                repo.name_colored(),
+                branch.yellow()
+            ),
+            Change::Grant {
+                user,
+                repo,
+                read_only,
+            } => write!(
+                f,
+                "{} grant {} {}access to {}",
+                add,
+                user.login_colored(),
+                if *read_only { "read-only " } else { "" },
+                repo.name_colored()
+            ),
+            Change::Revoke { user, repo } => write!(
+                f,
+                "{} revoke {} access to {}",
+                del,
+                user.login_colored(),
+                repo.name_colored()
+            ),
+            Change::MaintainerSet { user, repo } => write!(
+                f,
+                "{} set {} as maintainer in {}",
+                add,
+                user.login_colored(),
+                repo.name_colored()
+            ),
+            Change::MaintainerUnset { user, repo } => write!(
+                f,
+                "{} unset {} as maintainer in {}",
+                del,
+                user.login_colored(),
+                repo.name_colored()
+            ),
+        }
+    }
+}
+
+impl Change {
+    pub fn apply(&self) -> DResult<()> {
+        match self {
+            Change::CreateUser { user, name, keys } => {
+                let mut key = keys.join("\n");
+                key.push('\n');
+                user.create_with_key(name, &key)?;
+            }
+            Change::SetUserName { user, name } => user.set_name(name)?,
+            Change::SetUserKeys { user, keys } => user.set_keys(keys)?,
+            Change::CreateRepo { repo, description } => {
+                repo.create(false, description.as_deref())?;
+            }
+            Change::SetDescription { repo, description } => {
+                repo.set_description(Some(description))?;
+            }
+            Change::Protect { repo, branch } => repo.protect(branch)?,
+            Change::Unprotect { repo, branch } => repo.unprotect(branch)?,
+            Change::RciSet {
+                repo,
+                branch,
+                url,
+                secret,
+            } => repo.rci_set(branch, url, secret)?,
+            Change::RciUnset { repo, branch } => repo.rci_unset(branch)?,
+            Change::Grant {
+                user,
+                repo,
+                read_only,
+            } => user.grant(repo, *read_only)?,
+            Change::Revoke { user, repo } => user.revoke(repo)?,
+            Change::MaintainerSet { user, repo } => user.maintainer_set(repo)?,
+            Change::MaintainerUnset { user, repo } => {
+                if is_namespace_maintainer(repo, user.login())? {
+                    // the role set by namespaces is kept
+                    repo.set_flag(
+                        &format!("gmg.user.{}.directmaintainer", user.login()),
+                        false,
+                    )?;
+                } else {
+                    user.maintainer_unset(repo)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_namespace_maintainer(repo: &Repository, login: &str) -> DResult<bool> {
+    for ns in Namespace::for_repo(repo)? {
+        if ns.maintainers()?.iter().any(|m| m == login) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Current state of a repository, for newly created ones the state after creation is assumed
+fn current_repo_state(repo: &Repository) -> DResult<RepoState> {
+    if repo.exists().is_err() {
+        return Ok(RepoState {
+            protected: config().protected_branches.iter().cloned().collect(),
+            ..RepoState::default()
+        });
+    }
+    let config = repo.read_config()?;
+    Ok(RepoState {
+        description: repo.read_description()?,
+        protected: config.protected_branches().into_iter().collect(),
+        users: repo
+            .direct_users()?
+            .into_iter()
+            .map(|u| u.login().to_owned())
+            .collect(),
+        read_only: config.read_only_users().into_iter().collect(),
+        maintainers: repo.direct_maintainers()?.into_iter().collect(),
+        rci: config
+            .rci()
+            .into_iter()
+            .map(|r| {
+                (
+                    r.branch,
+                    RciState {
+                        url: r.url,
+                        secret: Some(r.secret),
+                    },
+                )
+            })
+            .collect(),
+    })
+}
+
+/// Parses the keys and checks them with the policy, new users require at least one key
+fn check_keys(login: &str, keys: &[String]) -> DResult<()> {
+    match parse_keys(&keys.join("\n")) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Box::new(match ErrorKind::from_boxed(e) {
+            ErrorKind::Parse(msg) => ErrorKind::Parse(format!("user {} keys: {}", login, msg)),
+            ErrorKind::Policy(msg) => ErrorKind::Policy(format!("user {} keys: {}", login, msg)),
+            e => e,
+        })),
+    }
+}
+
+impl State {
+    pub fn load(path: &Path) -> DResult<Self> {
+        let contents = fs::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|e| {
+            Box::new(ErrorKind::Parse(format!(
+                "invalid state file {}: {}",
+                path.to_string_lossy(),
+                e
+            ))) as Box<dyn std::error::Error>
+        })
+    }
+    /// Reads the state of the running server, RCI secrets are omitted unless with_secrets is set
+    pub fn export(with_secrets: bool) -> DResult<Self> {
+        let mut state = State::default();
+        for (login, name) in system().users()? {
+            let keys = login.parse::<User>()?.keys()?;
+            state.users.insert(login, UserState { name, keys });
+        }
+        for repo in Repository::list()? {
+            let mut repo_state = current_repo_state(&repo)?;
+            if !with_secrets {
+                for rci in repo_state.rci.values_mut() {
+                    rci.secret = None;
+                }
+            }
+            state.repos.insert(repo.name().to_owned(), repo_state);
+        }
+        Ok(state)
+    }
+    pub fn to_toml(&self) -> DResult<String> {
+        Ok(toml::to_string(self)?)
+    }
+    /// Computes changes required to bring the server to the state
+    pub fn plan(&self) -> DResult<Vec<Change>> {
+        let mut user_changes = Vec::new();
+        let mut repo_changes = Vec::new();
+        let mut access_changes = Vec::new();
+        for (login, desired) in &self.users {
+            let user: User = login.parse()?;
+            if !desired.keys.is_empty() || user.exists().is_err() {
+                check_keys(login, &desired.keys)?;
+            }
+            if user.exists().is_err() {
+                user_changes.push(Change::CreateUser {
+                    user,
+                    name: desired.name.clone(),
+                    keys: desired.keys.clone(),
+                });
+                continue;
+            }
+            if user.full_name()?.as_deref() != Some(desired.name.as_str()) {
+                user_changes.push(Change::SetUserName {
+                    user: user.clone(),
+                    name: desired.name.clone(),
+                });
+            }
+            if !desired.keys.is_empty() && user.keys()? != desired.keys {
+                user_changes.push(Change::SetUserKeys {
+                    user,
+                    keys: desired.keys.clone(),
+                });
+            }
+        }
+        for (name, desired) in &self.repos {
+            let repo: Repository = name.parse()?;
+            let current = current_repo_state(&repo)?;
+            if repo.exists().is_err() {
+                repo_changes.push(Change::CreateRepo {
+                    repo: repo.clone(),
+                    description: desired.description.clone(),
+                });
+            } else if let Some(ref description) = desired.description {
+                if current.description.as_ref() != Some(description) {
+                    repo_changes.push(Change::SetDescription {
+                        repo: repo.clone(),
+                        description: description.clone(),
+                    });
+                }
+            }
+            for branch in desired.protected.difference(&current.protected) {
+                repo_changes.push(Change::Protect {
+                    repo: repo.clone(),
+                    branch: branch.clone(),
+                });
+            }
+            for branch in current.protected.difference(&desired.protected) {
+                repo_changes.push(Change::Unprotect {
+                    repo: repo.clone(),
+                    branch: branch.clone(),
+                });
+            }
+            for (branch, rci) in &desired.rci {
+                let current_rci = current.rci.get(branch);
+                let Some(secret) = rci
+                    .secret
+                    .as_ref()
+                    .or_else(|| current_rci.and_then(|r| r.secret.as_ref()))
+                else {
+                    return failed!(
+                        Parse,
+                        format!("repository {}: RCI branch {} requires secret", name, branch)
+                    );
+                };
+                if current_rci.is_none_or(|r| r.url != rci.url || r.secret.as_ref() != Some(secret))
+                {
+                    repo_changes.push(Change::RciSet {
+                        repo: repo.clone(),
+                        branch: branch.clone(),
+                        url: rci.url.clone(),
+                        secret: secret.clone(),
+                    });
+                }
+            }
+            for branch in current.rci.keys() {
+                if !desired.rci.contains_key(branch) {
+                    repo_changes.push(Change::RciUnset {
+                        repo: repo.clone(),
+                        branch: branch.clone(),
+                    });
+                }
+            }
+            for login in desired.users.union(&desired.maintainers) {
+                let user: User = login.parse()?;
+                if !self.users.contains_key(login) && user.exists().is_err() {
+                    return failed!(
+                        NotFound,
+                        format!("User {} (repository {}) doesn't exist", login, name)
+                    );
+                }
+            }
+            if let Some(login) = desired.read_only.difference(&desired.users).next() {
+                return failed!(
+                    Parse,
+                    format!(
+                        "repository {}: read-only user {} is not in users",
+                        name, login
+                    )
+                );
+            }
+            for login in &desired.users {
+                let read_only = desired.read_only.contains(login);
+                if !current.users.contains(login) || current.read_only.contains(login) != read_only
+                {
+                    access_changes.push(Change::Grant {
+                        user: login.parse()?,
+                        repo: repo.clone(),
+                        read_only,
+                    });
+                }
+            }
+            for login in current.users.difference(&desired.users) {
+                access_changes.push(Change::Revoke {
+                    user: login.parse()?,
+                    repo: repo.clone(),
+                });
+            }
+            for login in desired.maintainers.difference(&current.maintainers) {
+                access_changes.push(Change::MaintainerSet {
+                    user: login.parse()?,
+                    repo: repo.clone(),
+                });
+            }
+            for login in current.maintainers.difference(&desired.maintainers) {
+                access_changes.push(Change::MaintainerUnset {
+                    user: login.parse()?,
+                    repo: repo.clone(),
+                });
+            }
+        }
+        user_changes.extend(repo_changes);
+        user_changes.extend(access_changes);
+        Ok(user_changes)
+    }
+    pub fn print_plan(&self) -> DResult<()> {
+        let changes = self.plan()?;
+        if changes.is_empty() {
+            println!("No changes");
+        } else {
+            for change in &changes {
+                println!("{}", change);
+            }
+            println!("Changes: {}", changes.len());
+        }
+        Ok(())
+    }
+    pub fn apply(&self) -> DResult<()> {
+        let changes = self.plan()?;
+        if changes.is_empty() {
+            println!("No changes");
+        }
+        for change in changes {
+            change.apply()?;
+        }
+        Ok(())
+    }
+}
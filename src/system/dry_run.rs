@@ -0,0 +1,139 @@
+use super::System;
+use crate::ops::report;
+use crate::DResult;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[derive(Default)]
+struct Planned {
+    users_added: BTreeSet<String>,
+    users_deleted: BTreeSet<String>,
+    groups_added: BTreeSet<String>,
+    groups_deleted: BTreeSet<String>,
+}
+
+/// Reports changes instead of applying them, the planned accounts and groups are visible to the
+/// following reads
+pub struct DryRun {
+    inner: Arc<dyn System>,
+    planned: Mutex<Planned>,
+}
+
+impl DryRun {
+    pub fn new(inner: Arc<dyn System>) -> Self {
+        Self {
+            inner,
+            planned: <_>::default(),
+        }
+    }
+    fn planned(&self) -> MutexGuard<'_, Planned> {
+        self.planned.lock().unwrap()
+    }
+}
+
+impl System for DryRun {
+    fn user_exists(&self, login: &str) -> DResult<bool> {
+        let planned = self.planned();
+        if planned.users_added.contains(login) {
+            Ok(true)
+        } else if planned.users_deleted.contains(login) {
+            Ok(false)
+        } else {
+            self.inner.user_exists(login)
+        }
+    }
+    fn user_add(&self, login: &str, home: &Path, full_name: &str) -> DResult<()> {
+        report(format!(
+            "create user {} ({}), home: {}",
+            login,
+            full_name,
+            home.to_string_lossy()
+        ));
+        let mut planned = self.planned();
+        planned.users_deleted.remove(login);
+        planned.users_added.insert(login.to_owned());
+        Ok(())
+    }
+    fn user_del(&self, login: &str) -> DResult<()> {
+        report(format!("delete user {}", login));
+        let mut planned = self.planned();
+        planned.users_added.remove(login);
+        planned.users_deleted.insert(login.to_owned());
+        Ok(())
+    }
+    fn user_set_name(&self, login: &str, full_name: &str) -> DResult<()> {
+        report(format!("set user {} name: {}", login, full_name));
+        Ok(())
+    }
+    fn users(&self) -> DResult<Vec<(String, String)>> {
+        self.inner.users()
+    }
+    fn user_groups(&self, login: &str) -> DResult<Vec<String>> {
+        if self.planned().users_added.contains(login) {
+            Ok(Vec::new())
+        } else {
+            self.inner.user_groups(login)
+        }
+    }
+    fn groups(&self) -> DResult<Vec<String>> {
+        let planned = self.planned();
+        let mut groups: BTreeSet<String> = self
+            .inner
+            .groups()?
+            .into_iter()
+            .filter(|g| !planned.groups_deleted.contains(g))
+            .collect();
+        groups.extend(planned.groups_added.iter().cloned());
+        Ok(groups.into_iter().collect())
+    }
+    fn group_exists(&self, group: &str) -> DResult<bool> {
+        let planned = self.planned();
+        if planned.groups_added.contains(group) {
+            Ok(true)
+        } else if planned.groups_deleted.contains(group) {
+            Ok(false)
+        } else {
+            self.inner.group_exists(group)
+        }
+    }
+    fn group_add(&self, group: &str) -> DResult<()> {
+        report(format!("create group {}", group));
+        let mut planned = self.planned();
+        planned.groups_deleted.remove(group);
+        planned.groups_added.insert(group.to_owned());
+        Ok(())
+    }
+    fn group_del(&self, group: &str) -> DResult<()> {
+        report(format!("delete group {}", group));
+        let mut planned = self.planned();
+        planned.groups_added.remove(group);
+        planned.groups_deleted.insert(group.to_owned());
+        Ok(())
+    }
+    fn group_members(&self, group: &str) -> DResult<Vec<String>> {
+        if self.planned().groups_added.contains(group) {
+            Ok(Vec::new())
+        } else {
+            self.inner.group_members(group)
+        }
+    }
+    fn group_add_member(&self, group: &str, login: &str) -> DResult<()> {
+        report(format!("add user {} to group {}", login, group));
+        Ok(())
+    }
+    fn group_del_member(&self, group: &str, login: &str) -> DResult<()> {
+        report(format!("remove user {} from group {}", login, group));
+        Ok(())
+    }
+    fn chown(&self, path: &Path, user: &str, group: Option<&str>, recursive: bool) -> DResult<()> {
+        report(format!(
+            "chown{} {}{} {}",
+            if recursive { " -R" } else { "" },
+            user,
+            group.map(|g| format!(":{}", g)).unwrap_or_default(),
+            path.to_string_lossy()
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use super::{set_system, DryRun, Posix, System};
+use crate::config::{set_config, Config};
+use crate::{failed, DResult, ErrorKind, DRY_RUN};
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{atomic, Arc, Mutex, MutexGuard, PoisonError};
+use tempdir::TempDir;
+
+lazy_static! {
+    // the config and the system are process-wide, so tests are run one by one
+    static ref TEST_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// In-memory passwd/group database, file ownership is ignored
+#[derive(Default)]
+pub struct Fake {
+    users: Mutex<BTreeMap<String, String>>,
+    groups: Mutex<BTreeMap<String, Vec<String>>>,
+}
+
+impl Fake {
+    fn passwd(&self) -> MutexGuard<'_, BTreeMap<String, String>> {
+        self.users.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    fn group_db(&self) -> MutexGuard<'_, BTreeMap<String, Vec<String>>> {
+        self.groups.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl System for Fake {
+    fn user_exists(&self, login: &str) -> DResult<bool> {
+        Ok(self.passwd().contains_key(login))
+    }
+    fn user_add(&self, login: &str, home: &Path, full_name: &str) -> DResult<()> {
+        let mut users = self.passwd();
+        if users.contains_key(login) {
+            return failed!(AlreadyExists, format!("user {} already exists", login));
+        }
+        fs::create_dir_all(home)?;
+        users.insert(login.to_owned(), full_name.to_owned());
+        Ok(())
+    }
+    fn user_del(&self, login: &str) -> DResult<()> {
+        if self.passwd().remove(login).is_none() {
+            return failed!(NotFound, format!("user {} does not exist", login));
+        }
+        for members in self.group_db().values_mut() {
+            members.retain(|m| m != login);
+        }
+        Ok(())
+    }
+    fn user_set_name(&self, login: &str, full_name: &str) -> DResult<()> {
+        let mut users = self.passwd();
+        let Some(name) = users.get_mut(login) else {
+            return failed!(NotFound, format!("user {} does not exist", login));
+        };
+        full_name.clone_into(name);
+        Ok(())
+    }
+    fn users(&self) -> DResult<Vec<(String, String)>> {
+        Ok(self
+            .passwd()
+            .iter()
+            .map(|(login, name)| (login.clone(), name.clone()))
+            .collect())
+    }
+    fn user_groups(&self, login: &str) -> DResult<Vec<String>> {
+        if !self.passwd().contains_key(login) {
+            return failed!(NotFound, format!("user {} does not exist", login));
+        }
+        Ok(self
+            .group_db()
+            .iter()
+            .filter(|(_, members)| members.iter().any(|m| m == login))
+            .map(|(group, _)| group.clone())
+            .collect())
+    }
+    fn groups(&self) -> DResult<Vec<String>> {
+        Ok(self.group_db().keys().cloned().collect())
+    }
+    fn group_exists(&self, group: &str) -> DResult<bool> {
+        Ok(self.group_db().contains_key(group))
+    }
+    fn group_add(&self, group: &str) -> DResult<()> {
+        let mut groups = self.group_db();
+        if groups.contains_key(group) {
+            return failed!(AlreadyExists, format!("group {} already exists", group));
+        }
+        groups.insert(group.to_owned(), Vec::new());
+        Ok(())
+    }
+    fn group_del(&self, group: &str) -> DResult<()> {
+        if self.group_db().remove(group).is_none() {
+            return failed!(NotFound, format!("group {} does not exist", group));
+        }
+        Ok(())
+    }
+    fn group_members(&self, group: &str) -> DResult<Vec<String>> {
+        Ok(self.group_db().get(group).cloned().unwrap_or_default())
+    }
+    fn group_add_member(&self, group: &str, login: &str) -> DResult<()> {
+        if !self.passwd().contains_key(login) {
+            return failed!(NotFound, format!("user {} does not exist", login));
+        }
+        let mut groups = self.group_db();
+        let Some(members) = groups.get_mut(group) else {
+            return failed!(NotFound, format!("group {} does not exist", group));
+        };
+        if !members.iter().any(|m| m == login) {
+            members.push(login.to_owned());
+        }
+        Ok(())
+    }
+    fn group_del_member(&self, group: &str, login: &str) -> DResult<()> {
+        if let Some(members) = self.group_db().get_mut(group) {
+            members.retain(|m| m != login);
+        }
+        Ok(())
+    }
+    fn chown(
+        &self,
+        path: &Path,
+        _user: &str,
+        _group: Option<&str>,
+        _recursive: bool,
+    ) -> DResult<()> {
+        fs::symlink_metadata(path)?;
+        Ok(())
+    }
+}
+
+/// Installs the fake system and a config rooted in a temporary directory, the previous state is
+/// restored on drop
+pub struct TestEnv {
+    pub system: Arc<Fake>,
+    dir: TempDir,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl TestEnv {
+    pub fn new() -> Self {
+        let lock = TEST_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let dir = TempDir::new("gmg-test").unwrap();
+        let config = Config {
+            git_path: dir.path().join("git"),
+            home_path: dir.path().join("home"),
+            // the hooks of test repositories allow everything
+            gmg_path: Some(PathBuf::from("/bin/true")),
+            ..Config::default()
+        };
+        fs::create_dir_all(config.git_path.join(".config/cgit")).unwrap();
+        fs::create_dir_all(&config.home_path).unwrap();
+        set_config(config);
+        let system = Arc::new(Fake::default());
+        set_system(system.clone());
+        for (var, value) in [
+            ("GIT_AUTHOR_NAME", "gmg"),
+            ("GIT_AUTHOR_EMAIL", "gmg@localhost"),
+            ("GIT_COMMITTER_NAME", "gmg"),
+            ("GIT_COMMITTER_EMAIL", "gmg@localhost"),
+        ] {
+            std::env::set_var(var, value);
+        }
+        Self {
+            system,
+            dir,
+            _lock: lock,
+        }
+    }
+    /// Switches to dry-run mode
+    pub fn dry_run(&self) {
+        DRY_RUN.store(true, atomic::Ordering::SeqCst);
+        set_system(Arc::new(DryRun::new(self.system.clone())));
+    }
+    /// Creates a file in the temporary directory
+    pub fn file(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TestEnv {
+    fn drop(&mut self) {
+        DRY_RUN.store(false, atomic::Ordering::SeqCst);
+        set_config(Config::default());
+        set_system(Arc::new(Posix));
+    }
+}
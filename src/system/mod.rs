@@ -0,0 +1,53 @@
+use crate::DResult;
+use lazy_static::lazy_static;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+mod dry_run;
+#[cfg(test)]
+pub mod fake;
+mod posix;
+
+pub use dry_run::DryRun;
+pub use posix::Posix;
+
+lazy_static! {
+    static ref SYSTEM: RwLock<Arc<dyn System>> = RwLock::new(Arc::new(Posix));
+}
+
+/// # Panics
+///
+/// Will panic if the system lock is poisoned
+pub fn system() -> Arc<dyn System> {
+    SYSTEM.read().unwrap().clone()
+}
+
+/// # Panics
+///
+/// Will panic if the system lock is poisoned
+pub fn set_system(system: Arc<dyn System>) {
+    *SYSTEM.write().unwrap() = system;
+}
+
+/// Account, group and file ownership operations of the host system
+pub trait System: Send + Sync {
+    fn user_exists(&self, login: &str) -> DResult<bool>;
+    /// Creates a git-shell account with the home directory
+    fn user_add(&self, login: &str, home: &Path, full_name: &str) -> DResult<()>;
+    fn user_del(&self, login: &str) -> DResult<()>;
+    fn user_set_name(&self, login: &str, full_name: &str) -> DResult<()>;
+    /// Lists git-shell accounts as (login, full name)
+    fn users(&self) -> DResult<Vec<(String, String)>>;
+    /// Lists groups the user is a member of
+    fn user_groups(&self, login: &str) -> DResult<Vec<String>>;
+    /// Lists all groups of the system
+    fn groups(&self) -> DResult<Vec<String>>;
+    fn group_exists(&self, group: &str) -> DResult<bool>;
+    fn group_add(&self, group: &str) -> DResult<()>;
+    fn group_del(&self, group: &str) -> DResult<()>;
+    fn group_members(&self, group: &str) -> DResult<Vec<String>>;
+    fn group_add_member(&self, group: &str, login: &str) -> DResult<()>;
+    /// Removes the user from the group, no error if the user is not a member
+    fn group_del_member(&self, group: &str, login: &str) -> DResult<()>;
+    fn chown(&self, path: &Path, user: &str, group: Option<&str>, recursive: bool) -> DResult<()>;
+}
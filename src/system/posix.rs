@@ -0,0 +1,120 @@
+use super::System;
+use crate::{cmd, failed, DResult, ErrorKind};
+use std::fs;
+use std::path::Path;
+
+const GROUP_FILE: &str = "/etc/group";
+const PASSWD_FILE: &str = "/etc/passwd";
+
+/// The real system, managed with shadow-utils
+pub struct Posix;
+
+fn require_root() -> DResult<()> {
+    if users::get_effective_uid() == 0 {
+        Ok(())
+    } else {
+        failed!(Permission, "root privileges are required".to_owned())
+    }
+}
+
+fn group_entry(group: &str) -> DResult<Option<String>> {
+    let groups = fs::read_to_string(GROUP_FILE)?;
+    Ok(groups
+        .lines()
+        .find(|line| line.split(':').next() == Some(group))
+        .map(ToOwned::to_owned))
+}
+
+impl System for Posix {
+    fn user_exists(&self, login: &str) -> DResult<bool> {
+        Ok(cmd!("id", login).run_any()?.success())
+    }
+    fn user_add(&self, login: &str, home: &Path, full_name: &str) -> DResult<()> {
+        require_root()?;
+        let git_shell = which::which("git-shell")?;
+        cmd!("useradd", "-m", "-d", home, "--shell", git_shell, login).run()?;
+        self.user_set_name(login, full_name)
+    }
+    fn user_del(&self, login: &str) -> DResult<()> {
+        require_root()?;
+        cmd!("userdel", login).run()?;
+        Ok(())
+    }
+    fn user_set_name(&self, login: &str, full_name: &str) -> DResult<()> {
+        require_root()?;
+        cmd!("chfn", "-f", full_name, login).run()?;
+        Ok(())
+    }
+    fn users(&self) -> DResult<Vec<(String, String)>> {
+        let passwd = fs::read_to_string(PASSWD_FILE)?;
+        let mut result = Vec::new();
+        for line in passwd.lines().filter(|l| l.ends_with("/git-shell")) {
+            let mut sp = line.split(':');
+            let login = sp.next().unwrap_or_default();
+            let name = sp.nth(3).unwrap_or_default().split(',').next().unwrap();
+            result.push((login.to_owned(), name.to_owned()));
+        }
+        Ok(result)
+    }
+    fn user_groups(&self, login: &str) -> DResult<Vec<String>> {
+        let out = cmd!("id", "-Gn", login).run()?.stdout;
+        Ok(out.split_whitespace().map(ToOwned::to_owned).collect())
+    }
+    fn groups(&self) -> DResult<Vec<String>> {
+        let groups = fs::read_to_string(GROUP_FILE)?;
+        Ok(groups
+            .lines()
+            .filter_map(|line| line.split(':').next())
+            .filter(|group| !group.is_empty())
+            .map(ToOwned::to_owned)
+            .collect())
+    }
+    fn group_exists(&self, group: &str) -> DResult<bool> {
+        Ok(group_entry(group)?.is_some())
+    }
+    fn group_add(&self, group: &str) -> DResult<()> {
+        require_root()?;
+        cmd!("groupadd", group).run()?;
+        Ok(())
+    }
+    fn group_del(&self, group: &str) -> DResult<()> {
+        require_root()?;
+        cmd!("groupdel", group).run()?;
+        Ok(())
+    }
+    fn group_members(&self, group: &str) -> DResult<Vec<String>> {
+        let mut result = Vec::new();
+        if let Some(entry) = group_entry(group)? {
+            for user in entry.split(':').nth(3).unwrap_or_default().split(',') {
+                if !user.is_empty() {
+                    result.push(user.to_owned());
+                }
+            }
+        }
+        Ok(result)
+    }
+    fn group_add_member(&self, group: &str, login: &str) -> DResult<()> {
+        require_root()?;
+        cmd!("gpasswd", "-a", login, group).run()?;
+        Ok(())
+    }
+    fn group_del_member(&self, group: &str, login: &str) -> DResult<()> {
+        require_root()?;
+        cmd!("gpasswd", "-d", login, group).run_any()?;
+        Ok(())
+    }
+    fn chown(&self, path: &Path, user: &str, group: Option<&str>, recursive: bool) -> DResult<()> {
+        require_root()?;
+        let owner = if let Some(g) = group {
+            format!("{}:{}", user, g)
+        } else {
+            user.to_owned()
+        };
+        if recursive {
+            cmd!("chown", "-R", owner, path).run()?;
+        } else {
+            cmd!("chown", owner, path).run()?;
+        }
+        Ok(())
+    }
+}
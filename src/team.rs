@@ -0,0 +1,266 @@
+use crate::config::config;
+use crate::output::{self, tsv_list, Format, Tabular};
+use crate::repo::Repository;
+use crate::system::system;
+use crate::user::User;
+use crate::{failed, DResult, ErrorKind};
+use bmart_derive::Sorting;
+use colored::{ColoredString, Colorize};
+use serde::Serialize;
+use std::str::FromStr;
+
+/// A named group of users, stored as a POSIX group with the team prefix. Teams are granted to
+/// repositories with gmg.team.<name>.granted repository config flag
+#[derive(Clone, Sorting)]
+#[sorting(id = "name")]
+pub struct Team {
+    name: String,
+    group: String,
+}
+
+#[derive(Serialize)]
+pub struct TeamInfo {
+    pub name: String,
+    pub members: Vec<String>,
+    pub repos: Vec<String>,
+}
+
+impl Tabular for TeamInfo {
+    fn header() -> &'static [&'static str] {
+        &["name", "members", "repos"]
+    }
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            tsv_list(&self.members),
+            tsv_list(&self.repos),
+        ]
+    }
+}
+
+impl FromStr for Team {
+    type Err = ErrorKind;
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        if name.is_empty()
+            || name.starts_with('-')
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+        {
+            return Err(ErrorKind::InvalidName(format!(
+                "invalid team name: {}",
+                name
+            )));
+        }
+        let config = config();
+        let max_len = config.max_team_name_len();
+        if name.len() > max_len {
+            return Err(ErrorKind::InvalidName(format!(
+                "team name is longer than {} chars",
+                max_len
+            )));
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            group: format!("{}{}", config.team_prefix, name),
+        })
+    }
+}
+
+impl Team {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn name_colored(&self) -> ColoredString {
+        self.name.blue().bold()
+    }
+    pub fn group(&self) -> &str {
+        &self.group
+    }
+    fn granted_key(&self) -> String {
+        format!("gmg.team.{}.granted", self.name)
+    }
+    pub fn exists(&self) -> DResult<()> {
+        if system().group_exists(self.group())? {
+            Ok(())
+        } else {
+            failed!(NotFound, format!("Team doesn't exist: {}", self.name))
+        }
+    }
+    pub fn list() -> DResult<Vec<Team>> {
+        let prefix = &config().team_prefix;
+        let mut result = Vec::new();
+        for group in system().groups()? {
+            if let Some(name) = group.strip_prefix(prefix.as_str()) {
+                result.push(name.parse::<Team>()?);
+            }
+        }
+        result.sort();
+        Ok(result)
+    }
+    pub fn members(&self) -> DResult<Vec<User>> {
+        self.exists()?;
+        let mut result = Vec::new();
+        for login in system().group_members(self.group())? {
+            result.push(login.parse::<User>()?);
+        }
+        result.sort();
+        Ok(result)
+    }
+    /// Repositories the team is granted to
+    pub fn repos(&self) -> DResult<Vec<Repository>> {
+        let mut result = Vec::new();
+        for repo in Repository::list()? {
+            if repo.read_config()?.teams().contains(&self.name) {
+                result.push(repo);
+            }
+        }
+        Ok(result)
+    }
+    pub fn create(&self) -> DResult<()> {
+        if self.exists().is_ok() {
+            return failed!(AlreadyExists, format!("Team already exists: {}", self.name));
+        }
+        system().group_add(self.group())?;
+        println!("Team created: {}", self.name_colored());
+        Ok(())
+    }
+    pub fn destroy(&self) -> DResult<()> {
+        self.exists()?;
+        for repo in self.repos()? {
+            self.revoke(&repo)?;
+        }
+        system().group_del(self.group())?;
+        println!("Team {}: {}", "destroyed".red().bold(), self.name_colored());
+        Ok(())
+    }
+    pub fn add(&self, user: &User) -> DResult<()> {
+        self.exists()?;
+        user.exists()?;
+        system().group_add_member(self.group(), user.login())?;
+        for repo in self.repos()? {
+            let config = repo.read_config()?;
+            // archived repositories are attached when unarchived
+            if config.is_archived() {
+                if !config.archived_members().iter().any(|m| m == user.login()) {
+                    repo.add("gmg.archive.member", user.login())?;
+                }
+            } else {
+                user.attach(&repo)?;
+            }
+        }
+        user.update_cgit()?;
+        println!(
+            "User {} has been {} to team {}",
+            user.login_colored(),
+            "added".green().bold(),
+            self.name_colored()
+        );
+        Ok(())
+    }
+    pub fn remove(&self, user: &User) -> DResult<()> {
+        self.exists()?;
+        system().group_del_member(self.group(), user.login())?;
+        for repo in self.repos()? {
+            user.detach_if_unused(&repo)?;
+        }
+        user.update_cgit()?;
+        println!(
+            "User {} has been {} from team {}",
+            user.login_colored(),
+            "removed".red().bold(),
+            self.name_colored()
+        );
+        Ok(())
+    }
+    pub fn grant(&self, repo: &Repository) -> DResult<()> {
+        self.exists()?;
+        repo.exists()?;
+        let config = repo.read_config()?;
+        // archived repositories have no group, the members are recorded as former ones and
+        // attached when unarchived
+        if config.is_archived() {
+            let former = config.archived_members();
+            for login in &former {
+                if repo.user_teams(login)?.is_empty() && repo.user_namespaces(login)?.is_empty() {
+                    repo.set_flag(&format!("gmg.user.{}.direct", login), true)?;
+                }
+            }
+            repo.set_flag(&self.granted_key(), true)?;
+            for user in self.members()? {
+                if !former.iter().any(|m| m == user.login()) {
+                    repo.add("gmg.archive.member", user.login())?;
+                }
+            }
+        } else {
+            // users with access before the grant keep it when the team is revoked
+            for user in repo.direct_users()? {
+                repo.set_flag(&format!("gmg.user.{}.direct", user.login()), true)?;
+            }
+            repo.set_flag(&self.granted_key(), true)?;
+            for user in self.members()? {
+                user.attach(repo)?;
+                user.update_cgit()?;
+            }
+        }
+        println!(
+            "Team {} has been {} access to {}",
+            self.name_colored(),
+            "granted".green().bold(),
+            repo.name_colored()
+        );
+        Ok(())
+    }
+    pub fn revoke(&self, repo: &Repository) -> DResult<()> {
+        self.exists()?;
+        repo.exists()?;
+        repo.set_flag(&self.granted_key(), false)?;
+        for user in self.members()? {
+            if user.detach_if_unused(repo)? {
+                user.update_cgit()?;
+            }
+        }
+        println!(
+            "Team {} has been {} access to {}",
+            self.name_colored(),
+            "revoked".red().bold(),
+            repo.name_colored()
+        );
+        Ok(())
+    }
+    pub fn info(&self) -> DResult<TeamInfo> {
+        Ok(TeamInfo {
+            name: self.name.clone(),
+            members: self
+                .members()?
+                .iter()
+                .map(|u| u.login().to_owned())
+                .collect(),
+            repos: self.repos()?.iter().map(|r| r.name().to_owned()).collect(),
+        })
+    }
+    pub fn print_all(short: bool, format: Format) -> DResult<()> {
+        let teams = Self::list()?;
+        if !format.is_human() {
+            let mut result = Vec::new();
+            for team in teams {
+                result.push(team.info()?);
+            }
+            return output::print_list(format, &result);
+        }
+        for team in teams {
+            if short {
+                println!("{}", team.name_colored());
+            } else {
+                let info = team.info()?;
+                println!(
+                    "{} (members: {}; repos: {})",
+                    team.name_colored(),
+                    info.members.join(", "),
+                    info.repos.join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+// Unix timestamps and their UTC representation, without time zone databases
+use crate::{failed, DResult, ErrorKind};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Formats the timestamp relative to the current time, e.g. "5m ago"
+pub fn ago(timestamp: u64) -> String {
+    let secs = now().saturating_sub(timestamp);
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = i64::from(month);
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = u32::try_from(doy - (153 * mp + 2) / 5 + 1).unwrap_or_default();
+    let month = u32::try_from(if mp < 10 { mp + 3 } else { mp - 9 }).unwrap_or_default();
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Formats the timestamp as "YYYY-MM-DD HH:MM:SS" (UTC)
+pub fn format_utc(timestamp: u64) -> String {
+    let days = i64::try_from(timestamp / 86400).unwrap_or_default();
+    let secs = timestamp % 86400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Parses a point in time: a duration before now ("30m", "12h", "7d", "2w"), a UTC date
+/// ("YYYY-MM-DD", optionally followed by " HH:MM[:SS]") or a Unix timestamp
+pub fn parse_time(s: &str) -> DResult<u64> {
+    let s = s.trim();
+    let invalid = || -> DResult<u64> { failed!(Parse, format!("invalid time: {}", s)) };
+    if let Ok(timestamp) = s.parse::<u64>() {
+        return Ok(timestamp);
+    }
+    for (suffix, secs) in [("m", 60), ("h", 3600), ("d", 86400), ("w", 7 * 86400)] {
+        if let Some(n) = s.strip_suffix(suffix).and_then(|n| n.parse::<u64>().ok()) {
+            let Some(duration) = n.checked_mul(secs) else {
+                return invalid();
+            };
+            return Ok(now().saturating_sub(duration));
+        }
+    }
+    let (date, time) = s.split_once([' ', 'T']).unwrap_or((s, "00:00"));
+    let (Ok(date), Ok(time)) = (
+        date.split('-')
+            .map(str::parse)
+            .collect::<Result<Vec<u32>, _>>(),
+        time.split(':')
+            .map(str::parse)
+            .collect::<Result<Vec<u64>, _>>(),
+    ) else {
+        return invalid();
+    };
+    let [year, month, day] = date[..] else {
+        return invalid();
+    };
+    let (hour, min, sec) = match time[..] {
+        [h, m] => (h, m, 0),
+        [h, m, s] => (h, m, s),
+        _ => return invalid(),
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || min > 59 || sec > 59 {
+        return invalid();
+    }
+    let Ok(days) = u64::try_from(days_from_civil(i64::from(year), month, day)) else {
+        return invalid();
+    };
+    let Some(days_secs) = days.checked_mul(86400) else {
+        return invalid();
+    };
+    Ok(days_secs + hour * 3600 + min * 60 + sec)
+}
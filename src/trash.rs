@@ -0,0 +1,195 @@
+use crate::config::config;
+use crate::namespace::Namespace;
+use crate::ops::{self, chmod};
+use crate::output::{self, tsv_list, Format, Tabular};
+use crate::repo::Repository;
+use crate::time::format_utc;
+use crate::webhook::hex;
+use crate::{failed, DResult, ErrorKind};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A destroyed repository, stored in the trash as <id>.git with the metadata in <id>.json
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TrashEntry {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub group: String,
+    /// former users, re-attached on restore
+    pub users: Vec<String>,
+    pub maintainers: Vec<String>,
+    pub deleted: u64,
+}
+
+impl Tabular for TrashEntry {
+    fn header() -> &'static [&'static str] {
+        &[
+            "id",
+            "name",
+            "description",
+            "group",
+            "users",
+            "maintainers",
+            "deleted",
+        ]
+    }
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.name.clone(),
+            self.description.clone().unwrap_or_default(),
+            self.group.clone(),
+            tsv_list(&self.users),
+            tsv_list(&self.maintainers),
+            self.deleted.to_string(),
+        ]
+    }
+}
+
+impl TrashEntry {
+    /// Collects the metadata of the repository to be destroyed
+    pub fn new(repo: &Repository) -> DResult<Self> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let seed = format!("{}:{:?}", repo.name(), time);
+        Ok(Self {
+            // sorted by time
+            id: format!(
+                "{}.{:09}-{}",
+                time.as_secs(),
+                time.subsec_nanos(),
+                hex(&Sha256::digest(seed.as_bytes())[..4])
+            ),
+            name: repo.name().to_owned(),
+            description: repo.read_description()?,
+            group: repo.group()?,
+            users: repo.users()?.iter().map(|u| u.login().to_owned()).collect(),
+            maintainers: repo.read_config()?.maintainers(),
+            deleted: time.as_secs(),
+        })
+    }
+    fn repo_path(&self) -> PathBuf {
+        config().trash_path().join(format!("{}.git", self.id))
+    }
+    fn meta_path(&self) -> PathBuf {
+        config().trash_path().join(format!("{}.json", self.id))
+    }
+    fn summary(&self) -> String {
+        let mut line = format!(
+            "{} {} deleted {}",
+            self.id,
+            self.name.cyan().bold(),
+            format_utc(self.deleted)
+        );
+        if let Some(ref description) = self.description {
+            line.push_str(&format!(" ({})", description));
+        }
+        if !self.users.is_empty() {
+            line.push_str(&format!(", users: {}", self.users.join(", ")));
+        }
+        line
+    }
+}
+
+/// Moves the repository directory to the trash, the trash is accessible by root only
+pub fn put(entry: &TrashEntry, repo_path: &Path) -> DResult<()> {
+    let trash_path = config().trash_path();
+    ops::create_dir_all(&trash_path)?;
+    chmod(&trash_path, 0o700)?;
+    ops::write(entry.meta_path(), serde_json::to_vec_pretty(entry)?)?;
+    ops::rename(repo_path, entry.repo_path())
+}
+
+/// Trash entries, the oldest first. Unreadable entries are skipped
+pub fn list() -> DResult<Vec<TrashEntry>> {
+    let trash_path = config().trash_path();
+    if !trash_path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut result: Vec<TrashEntry> = Vec::new();
+    for entry in fs::read_dir(trash_path)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "json") {
+            if let Ok(entry) = serde_json::from_slice(&fs::read(path)?) {
+                result.push(entry);
+            }
+        }
+    }
+    result.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(result)
+}
+
+pub fn print(format: Format) -> DResult<()> {
+    let entries = list()?;
+    if !format.is_human() {
+        return output::print_list(format, &entries);
+    }
+    for entry in entries {
+        println!("{}", entry.summary());
+    }
+    Ok(())
+}
+
+/// Restores the latest destroyed repository with the name (or the entry with the id), the group
+/// is recreated and the former users which still exist are granted access again
+pub fn restore(repo: &Repository, id: Option<&str>) -> DResult<()> {
+    let Some(entry) = list()?
+        .into_iter()
+        .rev()
+        .find(|e| e.name == repo.name() && id.is_none_or(|id| e.id == id))
+    else {
+        return failed!(NotFound, format!("{} not found in the trash", repo.name()));
+    };
+    if repo.exists().is_ok() {
+        return failed!(
+            AlreadyExists,
+            format!("repository already exists: {}", repo.name())
+        );
+    }
+    if let Some(parent) = repo.path().parent() {
+        ops::create_dir_all(parent)?;
+    }
+    ops::rename(entry.repo_path(), repo.path())?;
+    repo.recreate_group()?;
+    repo.fix(false)?;
+    repo.attach_former(&entry.users)?;
+    Namespace::apply_inherited(repo)?;
+    ops::remove_file(entry.meta_path())?;
+    println!(
+        "Repository {}: {}",
+        "restored".green().bold(),
+        repo.name_colored()
+    );
+    Ok(())
+}
+
+/// Permanently removes trash entries deleted before the time (all if not specified)
+pub fn purge(before: Option<u64>) -> DResult<()> {
+    let mut purged = 0;
+    for entry in list()? {
+        if before.is_some_and(|t| entry.deleted >= t) {
+            continue;
+        }
+        if entry.repo_path().exists() {
+            ops::remove_dir_all(entry.repo_path())?;
+        }
+        ops::remove_file(entry.meta_path())?;
+        println!(
+            "Repository {} {} from the trash ({})",
+            entry.name.cyan().bold(),
+            "purged".red().bold(),
+            entry.id
+        );
+        purged += 1;
+    }
+    if purged == 0 {
+        println!("Nothing to purge");
+    }
+    Ok(())
+}
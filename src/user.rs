@@ -1,13 +1,63 @@
-use crate::repo::Repository;
-use crate::{chmod, sh, sh_any, DResult, ErrorKind, GIT_PATH, GROUP_PFX, HOME_PATH};
+use crate::config::valid_branch_rule;
+use crate::key::{parse_keys, KeyInfo, PublicKey};
+use crate::namespace::Namespace;
+use crate::ops::{self, chmod};
+use crate::output::{self, tsv_list, Format, Tabular};
+use crate::repo::{Access, Repository};
+use crate::system::system;
+use crate::{config, err, failed, DResult, ErrorKind};
 use bmart_derive::Sorting;
 use colored::{ColoredString, Colorize};
-use std::env::set_current_dir;
+use serde::Serialize;
 use std::fs;
 use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+#[derive(Serialize)]
+pub struct UserInfo {
+    pub login: String,
+    pub name: String,
+    pub repos: Vec<String>,
+    pub maintainer: Vec<String>,
+}
+
+impl Tabular for UserInfo {
+    fn header() -> &'static [&'static str] {
+        &["login", "name", "repos", "maintainer"]
+    }
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.login.clone(),
+            self.name.clone(),
+            tsv_list(&self.repos),
+            tsv_list(&self.maintainer),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct UserRepoInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub access: Access,
+    pub maintainer: bool,
+}
+
+impl Tabular for UserRepoInfo {
+    fn header() -> &'static [&'static str] {
+        &["name", "description", "access", "maintainer"]
+    }
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.description.clone().unwrap_or_default(),
+            self.access.to_string(),
+            self.maintainer.to_string(),
+        ]
+    }
+}
+
 #[derive(Clone, Sorting)]
 #[sorting(id = "login")]
 pub struct User {
@@ -15,10 +65,43 @@ pub struct User {
     home: PathBuf,
 }
 
+/// Reads keys from the file, '-' for stdin
+fn read_key_file(key_file: &str) -> DResult<String> {
+    if key_file == "-" {
+        println!("Paste a public SSH key here, Ctrl+C to abort");
+        let mut key = String::new();
+        std::io::stdin().read_to_string(&mut key)?;
+        Ok(key)
+    } else {
+        Ok(fs::read_to_string(key_file)?)
+    }
+}
+
+/// Max login length, as accepted by useradd
+const MAX_LOGIN_LEN: usize = 32;
+
 impl FromStr for User {
     type Err = ErrorKind;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut path = HOME_PATH.clone();
+        // POSIX portable user names: logins are passed to system tools and used in paths and
+        // config keys
+        if !s
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+            || !s
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+        {
+            return Err(ErrorKind::InvalidName(format!("invalid login: {}", s)));
+        }
+        if s.len() > MAX_LOGIN_LEN {
+            return Err(ErrorKind::InvalidName(format!(
+                "login is longer than {} chars",
+                MAX_LOGIN_LEN
+            )));
+        }
+        let mut path = config().home_path.clone();
         path.push(s);
         Ok(Self {
             login: s.to_owned(),
@@ -37,41 +120,202 @@ impl User {
     pub fn home(&self) -> &Path {
         &self.home
     }
-    pub fn chdir(&self) -> DResult<()> {
-        set_current_dir(&self.home)?;
-        Ok(())
+    pub fn cgitrc_path(&self) -> PathBuf {
+        let mut path = config().git_path.clone();
+        path.push(format!(".config/cgit/{}.cgitrc", self.login()));
+        path
     }
     pub fn exists(&self) -> DResult<()> {
-        sh(&format!(r#"id "{}""#, self.login))?;
-        Ok(())
+        if system().user_exists(self.login())? {
+            Ok(())
+        } else {
+            failed!(NotFound, format!("User doesn't exist: {}", self.login))
+        }
     }
-    pub fn create(&self, name: &str, key_file: &str) -> DResult<()> {
-        let git_shell = which::which("git-shell")?;
-        let key = if key_file == "-" {
-            println!("Paste a public SSH key here, Ctrl+C to abort");
-            let mut stdin = std::io::stdin();
-            let mut key = String::new();
-            stdin.read_to_string(&mut key)?;
-            key
+    fn not_exists(&self) -> DResult<()> {
+        if system().user_exists(self.login())? {
+            failed!(
+                AlreadyExists,
+                format!("User already exists: {}", self.login)
+            )
         } else {
-            fs::read_to_string(key_file)?
-        };
-        sh(&format!(
-            r#"useradd -m --shell "{}" "{}""#,
-            git_shell.to_string_lossy(),
-            self.login()
-        ))?;
-        sh(&format!(r#"chfn -f "{}" "{}""#, name, self.login()))?;
+            Ok(())
+        }
+    }
+    pub fn create(&self, name: &str, key_file: &str) -> DResult<()> {
+        self.not_exists()?;
+        let key = read_key_file(key_file)?;
+        self.create_with_key(name, &key)
+    }
+    pub fn create_with_key(&self, name: &str, key: &str) -> DResult<()> {
+        self.not_exists()?;
+        let mut keys: Vec<String> = parse_keys(key)?.iter().map(ToString::to_string).collect();
+        keys.push(String::new());
+        let system = system();
+        system.user_add(self.login(), self.home(), name)?;
         chmod(self.home(), 0o700)?;
-        self.chdir()?;
-        fs::create_dir_all(".ssh")?;
-        fs::write(".ssh/authorized_keys", key)?;
-        chmod(".ssh", 0o700)?;
-        sh(&format!(r#"chown -R "{}" .ssh"#, self.login()))?;
+        let mut ssh_dir = self.home.clone();
+        ssh_dir.push(".ssh");
+        ops::create_dir_all(&ssh_dir)?;
+        ops::write(self.authorized_keys_path(), keys.join("\n"))?;
+        chmod(&ssh_dir, 0o700)?;
+        system.chown(&ssh_dir, self.login(), None, true)?;
         self.update_cgit()?;
         println!("User created: {}", self.login_colored());
         Ok(())
     }
+    pub fn authorized_keys_path(&self) -> PathBuf {
+        let mut path = self.home.clone();
+        path.push(".ssh/authorized_keys");
+        path
+    }
+    /// Reads the user's public keys, one per line
+    pub fn keys(&self) -> DResult<Vec<String>> {
+        let path = self.authorized_keys_path();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        Ok(fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(ToOwned::to_owned)
+            .collect())
+    }
+    pub fn set_keys(&self, keys: &[String]) -> DResult<()> {
+        self.exists()?;
+        let path = self.authorized_keys_path();
+        let mut contents = keys.join("\n");
+        contents.push('\n');
+        ops::write(&path, contents)?;
+        system().chown(&path, self.login(), None, false)?;
+        println!("User {} keys {}", self.login_colored(), "updated".green());
+        Ok(())
+    }
+    /// Parses the user's public keys, invalid lines are reported and skipped
+    pub fn public_keys(&self) -> DResult<Vec<PublicKey>> {
+        let mut result = Vec::new();
+        for line in self.keys()? {
+            match line.parse::<PublicKey>() {
+                Ok(key) => result.push(key),
+                Err(e) => {
+                    err!(format!("User {} invalid key: {}", self.login(), e));
+                }
+            }
+        }
+        Ok(result)
+    }
+    pub fn key_add(&self, key_file: &str) -> DResult<()> {
+        self.exists()?;
+        let new_keys = parse_keys(&read_key_file(key_file)?)?;
+        let current = self.public_keys()?;
+        for key in &new_keys {
+            if current.iter().any(|k| k.fingerprint() == key.fingerprint()) {
+                return failed!(
+                    AlreadyExists,
+                    format!(
+                        "User {} already has the key {}",
+                        self.login(),
+                        key.fingerprint()
+                    )
+                );
+            }
+        }
+        let mut keys = self.keys()?;
+        keys.extend(new_keys.iter().map(ToString::to_string));
+        self.set_keys(&keys)?;
+        for key in new_keys {
+            println!(
+                "User {} key {}: {} {}",
+                self.login_colored(),
+                "added".green().bold(),
+                key.fingerprint(),
+                key.comment()
+            );
+        }
+        Ok(())
+    }
+    /// Removes the key by SHA256 fingerprint, key data or comment. A comment must match a single
+    /// key only
+    pub fn key_remove(&self, key_id: &str) -> DResult<()> {
+        self.exists()?;
+        let lines = self.keys()?;
+        let parsed: Vec<Option<PublicKey>> = lines.iter().map(|l| l.parse().ok()).collect();
+        let mut matches: Vec<bool> = parsed
+            .iter()
+            .map(|k| k.as_ref().is_some_and(|k| k.is_identified_by(key_id)))
+            .collect();
+        if !matches.contains(&true) {
+            matches = parsed
+                .iter()
+                .map(|k| k.as_ref().is_some_and(|k| k.comment() == key_id))
+                .collect();
+            let count = matches.iter().filter(|m| **m).count();
+            if count > 1 {
+                return failed!(format!(
+                    "User {} has {} keys with comment {}, specify the fingerprint",
+                    self.login(),
+                    count,
+                    key_id
+                ));
+            }
+        }
+        let mut keys = Vec::new();
+        let mut removed = Vec::new();
+        for ((line, key), matched) in lines.into_iter().zip(parsed).zip(matches) {
+            match key {
+                Some(key) if matched => removed.push(key),
+                _ => keys.push(line),
+            }
+        }
+        if removed.is_empty() {
+            return failed!(
+                NotFound,
+                format!("User {} has no key {}", self.login(), key_id)
+            );
+        }
+        self.set_keys(&keys)?;
+        for key in removed {
+            println!(
+                "User {} key {}: {} {}",
+                self.login_colored(),
+                "removed".red().bold(),
+                key.fingerprint(),
+                key.comment()
+            );
+        }
+        Ok(())
+    }
+    pub fn print_keys(&self, format: Format) -> DResult<()> {
+        self.exists()?;
+        let keys: Vec<KeyInfo> = self.public_keys()?.iter().map(PublicKey::info).collect();
+        if !format.is_human() {
+            return output::print_list(format, &keys);
+        }
+        for key in keys {
+            println!(
+                "{} {} {} {}",
+                key.key_type.cyan(),
+                key.bits,
+                key.fingerprint.yellow(),
+                key.comment
+            );
+        }
+        Ok(())
+    }
+    pub fn full_name(&self) -> DResult<Option<String>> {
+        Ok(system()
+            .users()?
+            .into_iter()
+            .find(|(login, _)| login == self.login())
+            .map(|(_, name)| name))
+    }
+    pub fn set_name(&self, name: &str) -> DResult<()> {
+        self.exists()?;
+        system().user_set_name(self.login(), name)?;
+        println!("User {} name set: {}", self.login_colored(), name);
+        Ok(())
+    }
     pub fn update(&self) -> DResult<()> {
         self.update_cgit()?;
         Ok(())
@@ -92,21 +336,16 @@ impl User {
             }
         }
         config.push(String::new());
-        let mut path = GIT_PATH.clone();
-        path.push(format!(".config/cgit/{}.cgitrc", self.login()));
-        fs::write(path, config.join("\n"))?;
+        ops::write(self.cgitrc_path(), config.join("\n"))?;
         Ok(())
     }
     pub fn repos(&self) -> DResult<Vec<Repository>> {
         self.exists()?;
-        let out = sh(&format!(r#"groups "{}""#, self.login()))?;
+        let mut groups = Repository::groups()?;
         let mut result = Vec::new();
-        if let Some(groups) = out.split(':').nth(1) {
-            for group in groups.split(' ') {
-                let grp = group.trim();
-                if let Some(repo) = grp.strip_prefix(GROUP_PFX) {
-                    result.push(repo.parse::<Repository>()?);
-                }
+        for group in system().user_groups(self.login())? {
+            if let Some(repo) = groups.remove(&group) {
+                result.push(repo);
             }
         }
         result.sort();
@@ -114,10 +353,9 @@ impl User {
     }
     pub fn destroy(&self) -> DResult<()> {
         self.exists()?;
-        sh(&format!(r#"userdel "{}""#, self.login()))?;
-        let mut path = GIT_PATH.clone();
-        path.push(format!(".config/cgit/{}.cgitrc", self.login()));
-        let _r = fs::remove_file(path);
+        system().user_del(self.login())?;
+        Namespace::remove_user(self.login())?;
+        let _r = ops::remove_file(self.cgitrc_path());
         println!(
             "User {}: {}",
             "destroyed".red().bold(),
@@ -129,50 +367,132 @@ impl User {
         );
         Ok(())
     }
-    pub fn grant(&self, repo: &Repository) -> DResult<()> {
+    /// Adds the user to the repository group and creates the home symlink
+    pub fn attach(&self, repo: &Repository) -> DResult<()> {
+        let system = system();
+        system.group_add_member(&repo.group()?, self.login())?;
+        let mut link = self.home.clone();
+        if let Some(pos) = repo.name().rfind('/') {
+            let mut base_dir = self.home.clone();
+            base_dir.push(&repo.name()[..pos]);
+            ops::create_dir_all(&base_dir)?;
+            let mut top_dir = self.home.clone();
+            top_dir.push(&repo.name()[..repo.name().find('/').unwrap()]);
+            system.chown(&top_dir, self.login(), None, true)?;
+        }
+        link.push(repo.name());
+        let _r = ops::remove_file(&link);
+        ops::symlink(repo.path(), link)?;
+        Ok(())
+    }
+    /// Removes the user from the repository group and removes the home symlink
+    pub fn detach(&self, repo: &Repository) -> DResult<()> {
+        system().group_del_member(&repo.group()?, self.login())?;
+        let mut link = self.home.clone();
+        link.push(repo.name());
+        let _r = ops::remove_file(link);
+        let mut top_dir = self.home.clone();
+        top_dir.push(repo.name().split('/').next().unwrap());
+        ops::remove_empty_dirs(top_dir);
+        Ok(())
+    }
+    /// Removes the access if it has been granted neither directly nor by teams or namespaces,
+    /// returns true if detached. For archived repositories the user is removed from the former
+    /// members
+    pub fn detach_if_unused(&self, repo: &Repository) -> DResult<bool> {
+        let config = repo.read_config()?;
+        let direct = config.direct_users().iter().any(|d| d == self.login());
+        if direct
+            || !repo.user_teams(self.login())?.is_empty()
+            || !repo.user_namespaces(self.login())?.is_empty()
+        {
+            return Ok(false);
+        }
+        if config.is_archived() {
+            if config.archived_members().iter().any(|m| m == self.login()) {
+                repo.unset_value("gmg.archive.member", self.login())?;
+            }
+            return Ok(false);
+        }
+        self.detach(repo)?;
+        Ok(true)
+    }
+    /// Grants access to the repository, read-only users can clone only, their ref updates are
+    /// denied by the update hook
+    pub fn grant(&self, repo: &Repository, read_only: bool) -> DResult<()> {
         self.exists()?;
         repo.exists()?;
-        sh(&format!(
-            r#"gpasswd -a "{}" "{}""#,
-            self.login(),
-            repo.group()
-        ))?;
-        self.chdir()?;
-        if let Some(pos) = repo.name().rfind('/') {
-            let base_dir = &repo.name()[..pos];
-            fs::create_dir_all(base_dir)?;
-            sh(&format!(
-                r#"chown -R "{}" "{}""#,
-                self.login(),
-                &repo.name()[..repo.name().find('/').unwrap()]
-            ))?;
-            set_current_dir(base_dir)?;
-        }
-        let _r = fs::remove_file(repo.short_name());
-        sh(&format!(
-            r#"ln -sf "{}" "{}""#,
-            repo.path_as_str(),
-            repo.short_name()
-        ))?;
+        self.attach(repo)?;
+        repo.set_flag(&format!("gmg.user.{}.direct", self.login()), true)?;
+        repo.set_flag(&format!("hooks.user.{}.readonly", self.login()), read_only)?;
         self.update_cgit()?;
         println!(
-            "User {} has been {} access to {}",
+            "User {} has been {} {}access to {}",
             self.login_colored(),
             "granted".green().bold(),
+            if read_only { "read-only " } else { "" },
             repo.name_colored()
         );
         Ok(())
     }
-    pub fn print_all(short: bool) -> DResult<()> {
-        let out = sh_any("grep /git-shell$ /etc/passwd")?;
+    fn repo_infos(&self) -> DResult<Vec<UserRepoInfo>> {
+        let mut result = Vec::new();
+        for repo in self.repos()? {
+            let config = repo.read_config()?;
+            result.push(UserRepoInfo {
+                name: repo.name().to_owned(),
+                description: repo.read_description()?,
+                access: Access::from_read_only(
+                    config.read_only_users().iter().any(|r| r == self.login()),
+                ),
+                maintainer: config.maintainers().iter().any(|m| m == self.login()),
+            });
+        }
+        Ok(result)
+    }
+    pub fn print_repos(&self, short: bool, format: Format) -> DResult<()> {
+        let repos = self.repo_infos()?;
+        if !format.is_human() {
+            return output::print_list(format, &repos);
+        }
+        for r in repos {
+            let name = r.name.cyan().bold();
+            let access = if r.access == Access::Read {
+                " [read-only]"
+            } else {
+                ""
+            };
+            if short {
+                println!("{}{}", name, access);
+            } else {
+                println!("{} ({}){}", name, r.description.unwrap_or_default(), access);
+            }
+        }
+        Ok(())
+    }
+    pub fn print_all(short: bool, format: Format) -> DResult<()> {
         let mut result = Vec::new();
-        for line in out.lines() {
-            let mut sp = line.split(':');
-            let login = sp.next().unwrap().parse::<User>()?;
-            let name = sp.nth(3).unwrap().split(',').next().unwrap();
-            result.push((login, name));
+        for (login, name) in system().users()? {
+            result.push((login.parse::<User>()?, name));
         }
         result.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        if !format.is_human() {
+            let mut infos = Vec::new();
+            for (user, name) in result {
+                let repos = user.repo_infos()?;
+                infos.push(UserInfo {
+                    login: user.login,
+                    name,
+                    maintainer: repos
+                        .iter()
+                        .filter(|r| r.maintainer)
+                        .map(|r| r.name.clone())
+                        .collect(),
+                    repos: repos.into_iter().map(|r| r.name).collect(),
+                });
+            }
+            return output::print_list(format, &infos);
+        }
         for (user, name) in result {
             if short {
                 println!("{}", user.login_colored());
@@ -182,20 +502,31 @@ impl User {
         }
         Ok(())
     }
+    /// Revokes the direct access, the access through teams and namespaces is kept
     pub fn revoke(&self, repo: &Repository) -> DResult<()> {
         self.exists()?;
-        sh_any(&format!(
-            r#"gpasswd -d "{}" "{}""#,
-            self.login(),
-            repo.group()
-        ))?;
-        self.chdir()?;
-        let _r = fs::remove_file(repo.name());
-        let top_dir = repo.name().split('/').next().unwrap();
-        sh_any(&format!(
-            r#"find "{}" -type d -exec rmdir {{}} \; > /dev/null 2>&1"#,
-            top_dir
-        ))?;
+        let mut sources = Vec::new();
+        if repo.exists().is_ok() {
+            repo.set_flag(&format!("gmg.user.{}.direct", self.login()), false)?;
+            repo.set_flag(&format!("hooks.user.{}.readonly", self.login()), false)?;
+            for team in repo.user_teams(self.login())? {
+                sources.push(format!("team {}", team.name()));
+            }
+            for ns in repo.user_namespaces(self.login())? {
+                sources.push(format!("namespace {}", ns.name()));
+            }
+        }
+        if !sources.is_empty() {
+            println!(
+                "User {} direct access to {} has been {}, access remains through: {}",
+                self.login_colored(),
+                repo.name_colored(),
+                "revoked".red().bold(),
+                sources.join(", ")
+            );
+            return Ok(());
+        }
+        self.detach(repo)?;
         self.update_cgit()?;
         println!(
             "User {} has been {} access to {}",
@@ -208,6 +539,7 @@ impl User {
     pub fn maintainer_set(&self, repo: &Repository) -> DResult<()> {
         self.exists()?;
         repo.set(&format!("hooks.user.{}.maintainer", self.login()), "true")?;
+        repo.set_flag(&format!("gmg.user.{}.directmaintainer", self.login()), true)?;
         println!(
             "User {} has been {} as maintainer in {}",
             self.login_colored(),
@@ -216,8 +548,67 @@ impl User {
         );
         Ok(())
     }
+    /// Makes the user a maintainer of the branch or the matching branches (a glob pattern) only
+    pub fn maintainer_set_branch(&self, repo: &Repository, branch: &str) -> DResult<()> {
+        self.exists()?;
+        if !valid_branch_rule(branch) {
+            return failed!(
+                InvalidName,
+                format!("invalid branch or pattern: {}", branch)
+            );
+        }
+        let key = format!("hooks.branch.{}.maintainer", branch);
+        if !repo
+            .read_config()?
+            .get_all(&key)
+            .iter()
+            .any(|m| m == self.login())
+        {
+            repo.add(&key, self.login())?;
+        }
+        println!(
+            "User {} has been {} as maintainer of {} in {}",
+            self.login_colored(),
+            "set".green().bold(),
+            branch.yellow(),
+            repo.name_colored()
+        );
+        Ok(())
+    }
+    pub fn maintainer_unset_branch(&self, repo: &Repository, branch: &str) -> DResult<()> {
+        let key = format!("hooks.branch.{}.maintainer", branch);
+        if !repo
+            .read_config()?
+            .get_all(&key)
+            .iter()
+            .any(|m| m == self.login())
+        {
+            return failed!(
+                NotFound,
+                format!(
+                    "User {} is not a maintainer of {} in {}",
+                    self.login(),
+                    branch,
+                    repo.name()
+                )
+            );
+        }
+        repo.unset_value(&key, self.login())?;
+        println!(
+            "User {} has been {} as maintainer of {} in {}",
+            self.login_colored(),
+            "unset".red().bold(),
+            branch.yellow(),
+            repo.name_colored()
+        );
+        Ok(())
+    }
     pub fn maintainer_unset(&self, repo: &Repository) -> DResult<()> {
         repo.unset(&format!("hooks.user.{}.maintainer", self.login()))?;
+        repo.set_flag(
+            &format!("gmg.user.{}.directmaintainer", self.login()),
+            false,
+        )?;
         println!(
             "User {} has been {} as maintainer in {}",
             self.login_colored(),
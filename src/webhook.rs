@@ -0,0 +1,229 @@
+use crate::hook::RefUpdate;
+use crate::repo_config::{Rci, Webhook, DEFAULT_WEBHOOK_TIMEOUT};
+use crate::time::now;
+use crate::{cmd, DResult};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// Max commits included into a push payload, the newest ones are kept
+const MAX_PAYLOAD_COMMITS: usize = 100;
+
+#[derive(Serialize)]
+pub struct Commit {
+    pub id: String,
+    pub author: String,
+    pub email: String,
+    pub timestamp: String,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct Payload {
+    pub event: String,
+    pub repository: String,
+    #[serde(rename = "ref", skip_serializing_if = "Option::is_none")]
+    pub refname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+    pub pusher: String,
+    pub commits: Vec<Commit>,
+    pub timestamp: u64,
+}
+
+/// HTTP delivery result, error is set if failed
+pub struct Response {
+    /// HTTP status code, if a response has been received
+    pub code: Option<u16>,
+    pub error: Option<String>,
+}
+
+pub fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// HMAC-SHA256 (RFC 2104)
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(data);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+/// The value of X-Gmg-Signature-256 header
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    format!("sha256={}", hex(&hmac_sha256(secret.as_bytes(), body)))
+}
+
+/// Commits added to the ref by the update, called after the refs have been updated
+pub fn commits(git_dir: &Path, update: &RefUpdate) -> DResult<Vec<Commit>> {
+    if update.is_delete() {
+        return Ok(Vec::new());
+    }
+    let max_count = format!("--max-count={}", MAX_PAYLOAD_COMMITS);
+    let format = "--format=%H%x1f%an%x1f%ae%x1f%aI%x1f%s";
+    let c = if update.is_create() {
+        // commits which are not reachable from other refs
+        cmd!(
+            "git",
+            "log",
+            "--reverse",
+            max_count,
+            format,
+            &update.newrev,
+            "--not",
+            format!("--exclude={}", update.refname),
+            "--all"
+        )
+    } else {
+        cmd!(
+            "git",
+            "log",
+            "--reverse",
+            max_count,
+            format,
+            format!("{}..{}", update.oldrev, update.newrev)
+        )
+    };
+    let out = c.dir(git_dir).run()?.stdout;
+    Ok(out
+        .lines()
+        .filter_map(|line| {
+            let mut sp = line.splitn(5, '\x1f');
+            Some(Commit {
+                id: sp.next()?.to_owned(),
+                author: sp.next()?.to_owned(),
+                email: sp.next()?.to_owned(),
+                timestamp: sp.next()?.to_owned(),
+                message: sp.next()?.to_owned(),
+            })
+        })
+        .collect())
+}
+
+impl Payload {
+    pub fn push(
+        git_dir: &Path,
+        repository: &str,
+        update: &RefUpdate,
+        pusher: &str,
+    ) -> DResult<Self> {
+        Ok(Self {
+            event: "push".to_owned(),
+            repository: repository.to_owned(),
+            refname: Some(update.refname.clone()),
+            before: Some(update.oldrev.clone()),
+            after: Some(update.newrev.clone()),
+            pusher: pusher.to_owned(),
+            commits: commits(git_dir, update)?,
+            timestamp: now(),
+        })
+    }
+    /// A test event, sent by "repo webhook test"
+    pub fn ping(repository: &str, pusher: &str) -> Self {
+        Self {
+            event: "ping".to_owned(),
+            repository: repository.to_owned(),
+            refname: None,
+            before: None,
+            after: None,
+            pusher: pusher.to_owned(),
+            commits: Vec::new(),
+            timestamp: now(),
+        }
+    }
+}
+
+/// Quotes the value for a curl config file
+fn curl_quote(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Posts the body with curl, HTTP errors and timeouts are returned as failed responses. The URL,
+/// the headers (which may contain secrets) and the body are passed in a curl config on stdin to
+/// keep them out of the process list
+fn post(url: &str, headers: Vec<String>, body: &[u8], timeout: u32) -> DResult<Response> {
+    let mut curl_config = format!("url = {}\n", curl_quote(url));
+    for header in headers {
+        curl_config.push_str(&format!("header = {}\n", curl_quote(&header)));
+    }
+    curl_config.push_str(&format!(
+        "data-raw = {}\n",
+        curl_quote(&String::from_utf8_lossy(body))
+    ));
+    let out = cmd!(
+        "curl",
+        "-sS",
+        "-o",
+        "/dev/null",
+        "-w",
+        "%{http_code}",
+        "--max-time",
+        timeout.to_string(),
+        "-X",
+        "POST",
+        "-H",
+        format!("User-Agent: gmg/{}", env!("CARGO_PKG_VERSION")),
+        "-K",
+        "-"
+    )
+    .input(curl_config)
+    .run_any()?;
+    let code: Option<u16> = out.stdout.trim().parse().ok().filter(|&c| c > 0);
+    let error = if out.success() && code.is_some_and(|c| (200..300).contains(&c)) {
+        None
+    } else if let Some(code) = code {
+        Some(format!("HTTP {}", code))
+    } else {
+        Some(out.stderr.trim().trim_start_matches("curl: ").to_owned())
+    };
+    Ok(Response { code, error })
+}
+
+/// Sends the JSON payload to the webhook, signed if the webhook has a secret
+pub fn send(webhook: &Webhook, event: &str, delivery_id: &str, body: Vec<u8>) -> DResult<Response> {
+    let mut headers = vec![
+        "Content-Type: application/json".to_owned(),
+        format!("X-Gmg-Event: {}", event),
+        format!("X-Gmg-Delivery: {}", delivery_id),
+    ];
+    if let Some(ref secret) = webhook.secret {
+        headers.push(format!("X-Gmg-Signature-256: {}", signature(secret, &body)));
+    }
+    post(&webhook.url, headers, &body, webhook.timeout)
+}
+
+/// Triggers the RCI job
+pub fn trigger_rci(rci: &Rci) -> DResult<Response> {
+    post(
+        &rci.url,
+        vec![format!("x-trigger-key:{}", rci.secret)],
+        &[],
+        DEFAULT_WEBHOOK_TIMEOUT,
+    )
+}